@@ -0,0 +1,148 @@
+// src/cache.rs
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+use crate::types::KitStatus;
+
+const STATUS_CACHE_FILE: &str = "status-cache.json";
+const LAST_USED_FILE: &str = "last-used.json";
+
+/// Root of everything gk keeps between runs (`~/.cache/gkm` on Linux).
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("gkm"))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedStatus {
+    fetched_at: i64,
+    status: KitStatus,
+}
+
+/// Last fetched `KitStatus` per target, team and job, stored as JSON in the cache dir.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StatusCache {
+    entries: HashMap<String, CachedStatus>,
+}
+
+impl StatusCache {
+    fn path() -> Option<PathBuf> {
+        cache_dir().map(|dir| dir.join(STATUS_CACHE_FILE))
+    }
+
+    /// Where a status was fetched from: `job` (`pipeline/job`) on `target` as `team`.
+    pub fn key(target: &str, team: Option<&str>, job: &str) -> String {
+        format!("{}/{}/{}", target, team.unwrap_or("-"), job)
+    }
+
+    /// Loads the cache, treating a missing or unreadable file as empty.
+    pub async fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Returns the cached status if it was fetched less than `ttl_secs` ago.
+    pub fn get(&self, key: &str, ttl_secs: u64) -> Option<KitStatus> {
+        let entry = self.entries.get(key)?;
+        let age = Utc::now().timestamp() - entry.fetched_at;
+        (age >= 0 && (age as u64) < ttl_secs).then(|| entry.status.clone())
+    }
+
+    pub fn insert(&mut self, key: String, status: KitStatus) {
+        self.entries.insert(key, CachedStatus {
+            fetched_at: Utc::now().timestamp(),
+            status,
+        });
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let Some(path) = Self::path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await
+                .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+        }
+        tokio::fs::write(&path, serde_json::to_vec_pretty(self)?).await
+            .with_context(|| format!("Failed to write status cache {}", path.display()))
+    }
+
+    /// Drops every cached status.
+    pub async fn clear() -> Result<()> {
+        if let Some(path) = Self::path() {
+            if path.exists() {
+                tokio::fs::remove_file(&path).await
+                    .with_context(|| format!("Failed to remove status cache {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The kit and target from the last successful run, used as prompt defaults.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LastUsed {
+    pub kit: Option<String>,
+    pub target: Option<String>,
+}
+
+impl LastUsed {
+    fn path() -> Option<PathBuf> {
+        cache_dir().map(|dir| dir.join(LAST_USED_FILE))
+    }
+
+    /// Loads the saved choices, treating a missing or unreadable file as empty.
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = Self::path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+        }
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(ci_status: &str) -> KitStatus {
+        KitStatus {
+            name: String::from("bosh"),
+            version: String::from("1.0.0"),
+            template_version: String::from("1.0.0"),
+            ci_status: String::from(ci_status),
+            last_build: String::from("never"),
+            drift: String::from("-"),
+        }
+    }
+
+    #[test]
+    fn statuses_are_kept_apart_by_target_team_and_job() {
+        let mut cache = StatusCache::default();
+        cache.insert(StatusCache::key("ci", None, "bosh/test-kit"), status("Passing"));
+        cache.insert(StatusCache::key("prod", Some("ops"), "bosh/test-kit"), status("Failed"));
+
+        let cached = |target, team, job| cache.get(&StatusCache::key(target, team, job), 60).map(|s| s.ci_status);
+        assert_eq!(cached("ci", None, "bosh/test-kit").as_deref(), Some("Passing"));
+        assert_eq!(cached("prod", Some("ops"), "bosh/test-kit").as_deref(), Some("Failed"));
+        assert_eq!(cached("prod", None, "bosh/test-kit"), None);
+        assert_eq!(cached("ci", None, "bosh-dev/test-kit"), None);
+        assert_eq!(cache.get(&StatusCache::key("ci", None, "bosh/test-kit"), 0), None);
+    }
+}
@@ -1,92 +1,650 @@
 use anyhow::{bail, Result, Context};
-use serde::Deserialize;
-use std::{env, path::{Path, PathBuf}};
-
-#[derive(Debug, Deserialize)]
-struct PipelineMeta {
-    target: Option<String>,
-    url: Option<String>,
-    team: Option<String>,
-    pipeline: Option<String>,
-    name: Option<String>,
-    exposed: Option<bool>,
-}
-
-fn find_ci_directory(kit: &str) -> Result<PathBuf> {
-    let current_dir = env::current_dir().context("Failed to get current directory")?;
-    
-    // Check common locations
-    let possible_paths = vec![
-        current_dir.join(kit).join("ci"),
-        current_dir.join("ci"),
-        current_dir.parent().map(|p| p.join("ci")).unwrap_or_default(),
-    ];
-    
-    for path in possible_paths {
-        if path.exists() {
-            return Ok(path);
-        }
-    }
-    
-    bail!("Could not find ci directory for kit {}", kit)
-}
-
-fn determine_settings_file(ci_dir: &Path) -> Result<PathBuf> {
-    if let Ok(target) = env::var("CONCOURSE_TARGET") {
-        let target_file = ci_dir.join(format!("settings-{}.yml", target.replace(['/', ' '], "-")));
-        if target_file.exists() {
-            return Ok(target_file);
-        }
-    }
-    
-    let default_settings = ci_dir.join("settings.yml");
-    if default_settings.exists() {
-        Ok(default_settings)
+use chrono::Utc;
+use semver::Version;
+use std::{env, path::PathBuf};
+
+/// The name a kit's pipeline gets when its meta doesn't set one (`pipeline_name_template`
+/// in the config), behind any `--pipeline-prefix`.
+fn conventional_pipeline_name(kit: &str) -> String {
+    prefixed_pipeline_name(&config().pipeline_name(kit))
+}
+
+/// Names of the pipelines currently set on the target.
+async fn list_pipelines(fly: &dyn FlyClient) -> Result<Vec<String>> {
+    let output = fly.pipelines().await.context("Failed to list pipelines")?;
+
+    if !output.success {
+        check_auth(fly, &output.stderr)?;
+        bail!("Failed to list pipelines: {}", output.stderr_lossy().trim());
+    }
+
+    let pipelines: Value = parse_fly_json(&output.stdout)
+        .context("Failed to parse pipeline list")?;
+    Ok(pipelines.as_array()
+        .map(|list| list.iter()
+            .filter_map(|p| p["name"].as_str().map(String::from))
+            .collect())
+        .unwrap_or_default())
+}
+
+/// The kit's job, its team and a fly client scoped to its target, or `None`
+/// when the kit has to be skipped.
+async fn resolve_status_job(kit: &Kit, fly: &dyn FlyClient, options: &CiOptions, known_pipelines: Option<&[String]>,
+                            progress: &MultiProgress) -> Result<Option<(String, Option<String>, Box<dyn FlyClient>)>> {
+    // With --no-spruce, trust the naming convention when fly knows the pipeline
+    let conventional = conventional_pipeline_name(&kit.name);
+    let resolved = if known_pipelines.is_some_and(|known| known.contains(&conventional)) {
+        let (target, team) = resolve_kit_target_with(config(), kit, options.target.as_deref(), options.team.as_deref(), || None);
+        Ok((conventional, target, team))
+    } else {
+        // spruce is a blocking call; keep it off the runtime so other kits progress
+        let kit = kit.clone();
+        let (target, team) = (options.target.clone(), options.team.clone());
+        tokio::task::spawn_blocking(move || {
+            let name = kit.pipeline_name()?;
+            let (target, team) = resolve_kit_target(&kit, target.as_deref(), team.as_deref());
+            anyhow::Ok((name, target, team))
+        }).await?
+    };
+    match resolved {
+        Ok((name, target, team)) => Ok(Some((resolve_job_ref(&kit.name, &name), team.clone(), fly.scoped(&target, team)))),
+        Err(e) => {
+            progress.suspend(|| println!("{}", warning(&format!("Skipping {}: {}", kit, e))));
+            Ok(None)
+        }
+    }
+}
+
+/// Fetches one kit's CI status from its `job`.
+async fn fetch_kit_status(kit: &Kit, job: &str, fly: &dyn FlyClient) -> Result<KitStatus> {
+    let output = fly.builds(job, 1)
+        .await
+        .context("Failed to fetch build status")?;
+
+    let (status, last_build) = if output.success {
+        let builds: Value = parse_fly_json(&output.stdout).unwrap_or(Value::Null);
+        let latest = &builds[0];
+        let last_build = latest["start_time"]
+            .as_i64()
+            .map(relative_build_time)
+            .unwrap_or_else(|| String::from("never"));
+
+        let status = match latest["status"].as_str().unwrap_or("unknown") {
+            "succeeded" => "Passing",
+            "failed" => "Failed",
+            "started" => "Running",
+            _ => "Unknown",
+        };
+        (status, last_build)
+    } else {
+        check_auth(fly, &output.stderr)?;
+        if is_not_found(&output.stderr_lossy()) {
+            // fly can't find the pipeline or job: it was never set, which isn't a failure
+            ("Not Deployed", String::from("never"))
+        } else {
+            ("Error", String::from("unknown"))
+        }
+    };
+
+    // Fetch pipeline config for version info
+    let config_output = fly.configure(&kit.name)
+        .await
+        .context("Failed to fetch pipeline config")?;
+
+    let config: Value = if config_output.success {
+        parse_fly_json(&config_output.stdout)
+            .context("Failed to parse pipeline config")?
     } else {
-        bail!("Missing settings.yml in {:?}", ci_dir)
-    }}
-use dialoguer::Select;
-use std::{thread, time::Duration, process::Command};
-use tabled::Table;
+        check_auth(fly, &config_output.stderr)?;
+        Value::Null
+    };
+
+    // Prefer what the pipeline reports, falling back to the kit's own kit.yml
+    let reported = |field: &str, local: &Option<Version>| config[field]
+        .as_str()
+        .map(String::from)
+        .or_else(|| local.as_ref().map(Version::to_string))
+        .unwrap_or_else(|| String::from("unknown"));
+    let version = reported("version", &kit.version);
+    let template_version = reported("template_version", &kit.template_version);
+
+    let drift = match template_drift(&version, &template_version) {
+        Some(true) => DRIFT_BEHIND,
+        Some(false) => DRIFT_CURRENT,
+        None => "-",
+    };
+
+    Ok(KitStatus {
+        name: kit.name.clone(),
+        version,
+        template_version,
+        ci_status: status.to_string(),
+        last_build,
+        drift: drift.to_string(),
+    })
+}
+
+/// Whether the kit version is behind its template version; `None` when either isn't semver.
+fn template_drift(version: &str, template_version: &str) -> Option<bool> {
+    let version = Version::parse(version.trim_start_matches('v')).ok()?;
+    let template_version = Version::parse(template_version.trim_start_matches('v')).ok()?;
+    Some(version < template_version)
+}
+
+/// Renders a build's `start_time` (unix seconds) relative to now.
+fn relative_build_time(started: i64) -> String {
+    match Utc::now().timestamp() - started {
+        s if s < 60 => String::from("just now"),
+        s if s < 3600 => format!("{}m ago", s / 60),
+        s if s < 86400 => format!("{}h ago", s / 3600),
+        s => format!("{}d ago", s / 86400),
+    }
+}
+
+/// The most recent build of `job`, or `None` if it has never run.
+async fn latest_build(fly: &dyn FlyClient, job: &str) -> Result<Option<Value>> {
+    let output = fly.builds(job, 1)
+        .await
+        .context("Failed to fetch build info")?;
+
+    if !output.success {
+        check_auth(fly, &output.stderr)?;
+        bail!("Failed to fetch build information: {}", output.stderr_lossy().trim());
+    }
+
+    let builds: Value = parse_fly_json(&output.stdout)
+        .context("Failed to parse build info")?;
+    Ok(builds.as_array().and_then(|builds| builds.first()).cloned())
+}
+
+/// Why a resource from `fly resources --json` can't be trusted as a build input,
+/// or `None` when its last check succeeded recently enough.
+fn resource_problem(resource: &Value, now: i64) -> Option<String> {
+    // Concourse 7 reports the last check as a build; older versions use flags
+    let check_status = resource["build"]["status"].as_str();
+    if matches!(check_status, Some("failed" | "errored" | "aborted")) || resource["failing_to_check"].as_bool() == Some(true) {
+        let error = resource["check_error"].as_str().map(|e| format!(": {}", e.trim())).unwrap_or_default();
+        return Some(format!("last check {}{}", check_status.unwrap_or("failed"), error));
+    }
+    match resource["last_checked"].as_i64() {
+        None | Some(0) => Some(String::from("never checked")),
+        Some(checked) if now - checked > STALE_RESOURCE_SECS => {
+            Some(format!("last checked {}", relative_build_time(checked)))
+        }
+        Some(_) => None,
+    }
+}
+
+/// Warns about the pipeline's resources that errored or haven't been checked
+/// lately, since builds against them tend to do nothing or fail.
+async fn warn_unchecked_resources(fly: &dyn FlyClient, pipeline: &str) -> Result<()> {
+    let output = fly.resources(pipeline).await.context("Failed to list resources")?;
+    if !output.success {
+        return Err(fly_error(fly, "list the resources of", pipeline, &output.stderr));
+    }
+    let resources: Value = parse_fly_json(&output.stdout).context("Failed to parse resource list")?;
+
+    let now = Utc::now().timestamp();
+    let problems: Vec<(String, String)> = resources.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|resource| {
+            let problem = resource_problem(resource, now)?;
+            Some((resource["name"].as_str().unwrap_or("?").to_string(), problem))
+        })
+        .collect();
+
+    if problems.is_empty() {
+        say!("{}", info("All resources checked recently"));
+        return Ok(());
+    }
+    println!("{}", warning(&format!("{} resource(s) in {} may be stale:", problems.len(), pipeline)));
+    for (name, problem) in problems {
+        println!("    {} {}", style(name).fg(palette().warning), style(problem).dim());
+    }
+    Ok(())
+}
+
+/// fly reports the per-job build number as the build's `name`.
+fn build_number(build: &Value) -> Option<&str> {
+    build["name"].as_str().or_else(|| build["number"].as_str())
+}
+
+fn is_build_active(build: &Value) -> bool {
+    matches!(build["status"].as_str(), Some("started" | "pending"))
+}
+
+/// fly's ways of saying the target's token is no good.
+fn is_auth_failure(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    ["token is expired", "token expired", "not authorized", "unauthorized", "please login", "please log in", "not logged in"]
+        .iter()
+        .any(|msg| stderr.contains(msg))
+}
+
+/// Fails with `CiError::NotAuthenticated`, naming `fly`'s target, when fly's stderr is an auth failure.
+fn check_auth(fly: &dyn FlyClient, stderr: &[u8]) -> Result<()> {
+    if is_auth_failure(&String::from_utf8_lossy(stderr)) {
+        return Err(CiError::NotAuthenticated { target: fly.target().to_string() }.into());
+    }
+    Ok(())
+}
+
+/// The pipeline's live config; one that was never set counts as empty.
+async fn live_pipeline(fly: &dyn FlyClient, pipeline: &str) -> Result<String> {
+    let output = fly.get_pipeline(pipeline)
+        .await
+        .context("Failed to fetch pipeline config")?;
+    if output.success {
+        Ok(output.stdout_lossy())
+    } else if is_not_found(&output.stderr_lossy()) {
+        Ok(String::new())
+    } else {
+        Err(fly_error(fly, "download the configuration of", pipeline, &output.stderr))
+    }
+}
+
+/// The `<pipeline>/<job>` status, trigger, watch and logs act on, from the
+/// config's `job_template` (`{pipeline}/test-kit` by default).
+fn resolve_job_ref(kit: &str, pipeline: &str) -> String {
+    config().job_ref(kit, pipeline)
+}
+
+/// `fly` pointed at the target and team `kit` resolves to.
+fn kit_fly(kit: &Kit, options: &CiOptions, fly: &dyn FlyClient) -> Box<dyn FlyClient> {
+    let (target, team) = resolve_kit_target(kit, options.target.as_deref(), options.team.as_deref());
+    fly.scoped(&target, team)
+}
+
+/// Prints what a streamed fly call kept (its `--tail`) and, if it failed, its
+/// stderr; `Ok(false)` means the build itself didn't succeed.
+fn finish_stream(fly: &dyn FlyClient, output: &crate::fly::FlyOutput) -> Result<bool> {
+    print!("{}", output.stdout_lossy());
+    if !output.success {
+        check_auth(fly, &output.stderr)?;
+        eprint!("{}", output.stderr_lossy());
+    }
+    Ok(output.success)
+}
+
+fn is_not_found(stderr: &str) -> bool {
+    stderr.to_lowercase().contains("not found")
+}
+
+/// Turns a failed fly call into an error, calling out pipelines that were never set.
+fn fly_error(fly: &dyn FlyClient, action: &str, pipeline: &str, stderr: &[u8]) -> anyhow::Error {
+    if let Err(e) = check_auth(fly, stderr) {
+        return e;
+    }
+    let stderr = String::from_utf8_lossy(stderr);
+    if is_not_found(&stderr) {
+        anyhow::anyhow!("Pipeline '{}' not found on target {}. Has it been set with 'gk repipe'?", pipeline, fly.target())
+    } else {
+        anyhow::anyhow!("Failed to {} '{}': {}", action, pipeline, stderr.trim())
+    }
+}
+use dialoguer::{Confirm, Select};
+use futures::stream::{self, StreamExt};
+use indicatif::{MultiProgress, ProgressBar};
+use std::process::Command;
+use tabled::{Table, Tabled, settings::{Remove, Width, location::ByColumnName, peaker::Priority}};
 use console::style;
-use tokio::process::Command as AsyncCommand;
 use serde_json::Value;
 use crate::{
-    ui::GenesisKitUI,
-    types::KitStatus,
-    constants::AVAILABLE_KITS,
+    say,
+    ui::{prompt_error, require_terminal, GenesisKitUI},
+    types::{JobStatus, KitStatus},
+    cache::StatusCache,
+    config::config,
+    kits::{apply_kit_filter, discover_kits, exclude_kits, kit_names, prefixed_pipeline_name, resolve_kit, resolve_kit_target, resolve_kit_target_with, Kit, SettingsLookup},
     ui::styles::*,
     ui::progress::create_progress_bar,
+    ui::diff::render_diff,
+    ui::format::OutputFormat,
+    process::RunExt,
+    fly::{check_extra_args, parse_fly_json, FlyClient, SET_PIPELINE_FLAGS, TRIGGER_JOB_FLAGS},
+    commands::repipe::{RepipeCommand, RepipeOptions},
+    flyrc::FlyRc,
+    spruce,
 };
 
+const DEFAULT_STATUS_CONCURRENCY: usize = 4;
+
+/// Appended to the CI status of rows served from the status cache.
+const CACHED_MARK: &str = "(cached)";
+/// Drift for kits behind, or level with, their template version.
+const DRIFT_BEHIND: &str = "behind template";
+const DRIFT_CURRENT: &str = "current";
+
+/// Resources checked longer ago than this get a warning under `--check-resources`.
+const STALE_RESOURCE_SECS: i64 = 24 * 60 * 60;
+
+/// Failures that map to their own process exit code.
+#[derive(Debug, thiserror::Error)]
+pub enum CiError {
+    #[error("Build #{build} of {job} is still running; pass --force to trigger another")]
+    BuildInProgress { job: String, build: String },
+    #[error("Not logged in to fly target '{target}' (the token is missing or expired); run `fly -t {target} login`")]
+    NotAuthenticated { target: String },
+}
+
+impl CiError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CiError::BuildInProgress { .. } => 3,
+            CiError::NotAuthenticated { .. } => 4,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CiOptions {
+    pub kit: Option<String>,
+    pub file: Option<PathBuf>,
+    /// Print the fly commands that change something instead of running them
+    pub dry_run: bool,
+    pub wide: bool,
+    pub cache_ttl: u64,
+    pub no_cache: bool,
+    pub no_spruce: bool,
+    pub team: Option<String>,
+    pub concurrency: usize,
+    pub force: bool,
+    pub format: OutputFormat,
+    pub refresh: u64,
+    pub kit_filter: Option<regex::Regex>,
+    pub exclude_kits: Vec<String>,
+    pub pipeline: Option<String>,
+    pub target: Option<String>,
+    /// `--env`: picks the settings file kit pipelines are merged with
+    pub env: Option<String>,
+    /// `--settings`: the settings file, relative to each kit's `ci/`
+    pub settings: Option<String>,
+    /// Keep only this many trailing lines of build output instead of streaming it
+    pub tail: Option<usize>,
+    /// Args after `--`, appended to `fly set-pipeline` (update, set-pipeline) or `fly trigger-job` (trigger)
+    pub fly_args: Vec<String>,
+    pub skip_checks: bool,
+    /// `--set-var NAME=VALUE` pairs for `ci configure`, applied in order
+    pub set_vars: Vec<(String, String)>,
+    /// Warn about stale or errored resources before triggering
+    pub check_resources: bool,
+    /// `ci logs --build`: show this build instead of the latest
+    pub build: Option<u64>,
+    /// `ci trigger --all`: trigger every kit's job without watching
+    pub all: bool,
+    /// `ci status --only-failing`: list only kits whose CI failed or errored
+    pub only_failing: bool,
+    /// `ci diff-config --run-scripts`: regenerate job files before the local merge
+    pub run_scripts: bool,
+}
+
+impl CiOptions {
+    /// In-flight status fetches (or `trigger --all` triggers); 1 goes kit by kit, 0 means the default of 4.
+    fn status_concurrency(&self) -> usize {
+        if self.concurrency == 0 { DEFAULT_STATUS_CONCURRENCY } else { self.concurrency }
+    }
+
+    /// How kit settings files are picked for `--settings`, `--env` and `--target`.
+    pub fn settings_lookup(&self) -> SettingsLookup {
+        SettingsLookup { settings: self.settings.clone(), env: self.env.clone(), target: self.target.clone() }
+    }
+
+    /// Warns when `--team` doesn't match any team configured in `.flyrc`.
+    pub fn warn_unknown_team(&self) {
+        let Some(team) = &self.team else { return };
+        let Ok(flyrc) = FlyRc::shared() else { return };
+        let teams = flyrc.teams();
+        if !teams.is_empty() && !teams.contains(team) {
+            println!("{}", warning(&format!(
+                "Team '{}' isn't configured for any .flyrc target (known: {}); fly may reject it",
+                team, teams.join(", "))));
+        }
+    }
+}
+
+/// One kit's row in the `trigger --all` summary.
+#[derive(Tabled)]
+struct TriggerOutcome {
+    #[tabled(rename = "Kit")]
+    kit: String,
+    #[tabled(rename = "Job")]
+    job: String,
+    #[tabled(rename = "Build")]
+    build: String,
+    #[tabled(rename = "Result")]
+    result: String,
+    #[tabled(skip)]
+    failed: bool,
+}
+
+/// The build number in `fly trigger-job`'s "started <job> #<n>".
+fn started_build(stdout: &str) -> Option<&str> {
+    let (_, rest) = stdout.rsplit_once('#')?;
+    let number = rest.trim_end();
+    (!number.is_empty() && number.chars().all(|c| c.is_ascii_digit())).then_some(number)
+}
+
+/// Triggers one kit's job for `trigger --all`, reporting failure as the outcome
+/// rather than an error so the rest of the batch carries on.
+async fn trigger_kit(kit: &Kit, fly: &dyn FlyClient, options: &CiOptions) -> TriggerOutcome {
+    let mut outcome = TriggerOutcome { kit: kit.name.clone(), job: String::from("-"), build: String::from("-"), result: String::new(), failed: true };
+    let result: Result<String> = async {
+        let fly = kit_fly(kit, options, fly);
+        let kit = kit.clone();
+        let pipeline = tokio::task::spawn_blocking(move || kit.pipeline_name()).await??;
+        outcome.job = resolve_job_ref(&outcome.kit, &pipeline);
+
+        if !options.force {
+            if let Some(build) = latest_build(fly.as_ref(), &outcome.job).await? {
+                if is_build_active(&build) {
+                    let number = build_number(&build).unwrap_or("?");
+                    outcome.build = number.to_string();
+                    bail!("skipped: build #{} already running (--force triggers anyway)", number);
+                }
+            }
+        }
+
+        let output = fly.trigger_job(&outcome.job, false).await?;
+        if !output.success {
+            check_auth(&*fly, &output.stderr)?;
+            bail!("{}", output.stderr_lossy().trim());
+        }
+        if options.dry_run {
+            return Ok(String::from("dry run"));
+        }
+        if let Some(number) = started_build(&output.stdout_lossy()) {
+            outcome.build = number.to_string();
+        }
+        Ok(String::from("triggered"))
+    }.await;
+
+    match result {
+        Ok(result) => {
+            outcome.result = result;
+            outcome.failed = false;
+        }
+        Err(e) => outcome.result = format!("{:#}", e),
+    }
+    outcome
+}
+
+/// A status row with the terminal styling the table shows; rows are fetched
+/// and cached as plain text.
+fn styled_status(status: KitStatus) -> KitStatus {
+    let (ci_status, cached) = match status.ci_status.strip_suffix(CACHED_MARK) {
+        Some(ci_status) => (ci_status.trim_end(), true),
+        None => (status.ci_status.as_str(), false),
+    };
+    let palette = palette();
+    let mut ci_status = match ci_status {
+        "Passing" => style(ci_status).fg(palette.success).to_string(),
+        "Failed" | "Error" => style(ci_status).fg(palette.error).to_string(),
+        "Running" => style(ci_status).fg(palette.warning).to_string(),
+        _ => style(ci_status).dim().to_string(),
+    };
+    if cached {
+        ci_status = format!("{} {}", ci_status, style(CACHED_MARK).dim());
+    }
+    let (name, drift) = match status.drift.as_str() {
+        DRIFT_BEHIND => (style(&status.name).fg(palette.warning).bold().to_string(), warning(DRIFT_BEHIND)),
+        DRIFT_CURRENT => (status.name.clone(), success(DRIFT_CURRENT)),
+        drift => (status.name.clone(), style(drift).dim().to_string()),
+    };
+    KitStatus { name, ci_status, drift, ..status }
+}
+
+/// Lays statuses out in the requested format, hiding "Last Build" from the table unless `--wide`.
+/// On a terminal the table is fitted to its width, cutting the widest cells
+/// short with an ellipsis; piped tables keep every value whole.
+/// `--only-failing` drops passing kits first, and says so when none are left.
+fn render_statuses(mut statuses: Vec<KitStatus>, options: &CiOptions) -> Result<String> {
+    if options.only_failing {
+        statuses.retain(KitStatus::is_failing);
+    }
+    if let Some(output) = options.format.serialize(&statuses)? {
+        return Ok(output);
+    }
+    if options.only_failing && statuses.is_empty() {
+        return Ok(success("All green: no kits are failing"));
+    }
+    let mut status_table = Table::new(statuses.into_iter().map(styled_status));
+    if !options.wide {
+        status_table.with(Remove::column(ByColumnName::new("Last Build")));
+    }
+    let term = console::Term::stdout();
+    if term.is_term() {
+        let (_, columns) = term.size();
+        status_table.with(Width::truncate(columns as usize).suffix("…").priority(Priority::max(true)));
+    }
+    Ok(status_table.to_string())
+}
+
+/// A kit's status and, when it was fetched rather than cached, the cache key to save it under.
+type FetchedStatus = (KitStatus, Option<String>);
+
+/// Fetches the CI status of each kit that has a pipeline, in `kits` order,
+/// honouring the cache, `--no-spruce` and concurrency settings in `options`.
+/// A bar in `progress` counts the kits off and is cleared before returning;
+/// anything printed meanwhile goes through `progress` so it can't tear the bar.
+pub async fn collect_statuses(kits: &[Kit], fly: &dyn FlyClient, options: &CiOptions, progress: &MultiProgress) -> Result<Vec<KitStatus>> {
+    if options.no_cache {
+        StatusCache::clear().await?;
+    }
+    let use_cache = options.cache_ttl > 0;
+    let mut cache = if use_cache { StatusCache::load().await } else { StatusCache::default() };
+
+    let known_pipelines = if options.no_spruce { Some(list_pipelines(fly).await?) } else { None };
+
+    // Fetch up to `concurrency` kits at once; `buffered` keeps the table in kit order
+    let cached_statuses = &cache;
+    let known_pipelines = known_pipelines.as_deref();
+    // Machine-readable output keeps stderr quiet too
+    let bar = if options.format.is_table() {
+        create_progress_bar(progress, kits.len() as u64, "Fetching CI status...")
+    } else {
+        ProgressBar::hidden()
+    };
+    let bar = &bar;
+    let results: Vec<Result<Option<FetchedStatus>>> = stream::iter(kits)
+        .map(|kit| async move {
+            let status = async {
+                let Some((job, team, fly)) = resolve_status_job(kit, fly, options, known_pipelines, progress).await? else {
+                    return Ok(None);
+                };
+                let key = StatusCache::key(fly.target(), team.as_deref(), &job);
+                if let Some(mut cached) = cached_statuses.get(&key, options.cache_ttl) {
+                    cached.ci_status = format!("{} {}", cached.ci_status, CACHED_MARK);
+                    return Ok(Some((cached, None)));
+                }
+                let status = fetch_kit_status(kit, &job, fly.as_ref()).await?;
+                Ok(Some((status, Some(key))))
+            }.await;
+            bar.inc(1);
+            status
+        })
+        .buffered(options.status_concurrency())
+        .collect()
+        .await;
+    bar.finish_and_clear();
+
+    let mut statuses = Vec::new();
+    for result in results {
+        let Some((kit_status, key)) = result? else { continue };
+        if let (true, Some(key)) = (use_cache, key) {
+            cache.insert(key, kit_status.clone());
+        }
+        statuses.push(kit_status);
+    }
+
+    if use_cache {
+        cache.save().await?;
+    }
+
+    Ok(statuses)
+}
+
 impl GenesisKitUI {
-    pub async fn manage_ci(&self) -> Result<()> {
+    pub async fn manage_ci(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
         // First check if fly CLI is available
-        self.check_fly_cli()?;
+        if !options.skip_checks {
+            self.check_fly_cli()?;
+        }
 
-        println!("\n{}\n", heading("🔧 CI CONFIGURATION"));
+        say!("\n{}\n", heading("🔧 CI CONFIGURATION"));
 
-        let actions = vec!["View Status", "Update Configuration", "Trigger Build", "View Logs"];
+        let actions = vec![
+            "View Status", "Update Configuration", "Trigger Build", "View Logs",
+            "Pause Pipeline", "Unpause Pipeline", "Expose Pipeline", "Hide Pipeline", "List Jobs",
+        ];
+        let hint = "a subcommand such as `gk ci status`";
+        require_terminal(hint)?;
         let action = Select::with_theme(&self.theme)
-            .with_prompt(&param("Select CI action"))
+            .with_prompt(param("Select CI action"))
             .items(&actions)
-            .interact()?;
+            .interact()
+            .map_err(|e| prompt_error(e, hint))?;
 
         match action {
-            0 => self.view_ci_status().await?,
-            1 => self.update_ci_config().await?,
-            2 => self.trigger_ci_build().await?,
-            3 => self.view_ci_logs().await?,
+            0 => self.view_ci_status(options, fly).await?,
+            1 => self.update_ci_config(options, fly).await?,
+            2 => self.trigger_ci_build(options, fly).await?,
+            3 => self.view_ci_logs(options, fly).await?,
+            4 => self.set_pipeline_paused(options, fly, true).await?,
+            5 => self.set_pipeline_paused(options, fly, false).await?,
+            6 => self.set_pipeline_exposed(options, fly, true).await?,
+            7 => self.set_pipeline_exposed(options, fly, false).await?,
+            8 => self.list_ci_jobs(options, fly).await?,
             _ => unreachable!(),
         }
 
         Ok(())
     }
 
+    /// Uses the kit given on the command line, or prompts for one.
+    pub(crate) fn select_kit(&self, kit: Option<&str>, prompt: &str) -> Result<Kit> {
+        let kits = discover_kits()?;
+        let kit = match kit {
+            Some(kit) => resolve_kit(kit, &kits)?,
+            None => {
+                require_terminal("--kit")?;
+                let index = Select::with_theme(&self.theme)
+                    .with_prompt(param(prompt))
+                    .items(&kits)
+                    .default(self.last_kit_index(&kit_names(&kits)))
+                    .interact()
+                    .map_err(|e| prompt_error(e, "--kit"))?;
+                kits[index].clone()
+            }
+        };
+        self.remember_kit(&kit.name);
+        Ok(kit)
+    }
+
     fn check_fly_cli(&self) -> Result<()> {
         let output = Command::new("fly")
             .arg("--version")
-            .output()
+            .run_output()
             .context("Failed to check fly CLI. Please ensure it's installed and in your PATH")?;
 
         if !output.status.success() {
@@ -95,260 +653,761 @@ impl GenesisKitUI {
         Ok(())
     }
 
-    async fn view_ci_status(&self) -> Result<()> {
-        println!("\n{}", heading("📊 CI STATUS"));
-        
-        // First get pipeline configuration and extract meta
-        let mut statuses = Vec::new();
-        
-        for kit in AVAILABLE_KITS {
-            // Find ci directory and read pipeline config
-            let ci_dir = find_ci_directory(kit)?;
-            let settings_file = determine_settings_file(&ci_dir)?;
-            
-            // Merge pipeline configuration using spruce
-            let base_yml = ci_dir.join("pipeline").join("base.yml");
-            if !base_yml.exists() {
-                println!("{}", style(format!("⚠️  Skipping {}: No pipeline/base.yml found", kit)).yellow());
-                continue;
-            }
-            
-            let merged_config = Command::new("spruce")
-                .arg("merge")
-                .arg("--fallback-append")
-                .arg(&base_yml)
-                .arg(&settings_file)
-                .output()
-                .context("Failed to merge pipeline config")?;
-                
-            if !merged_config.status.success() {
-                println!("{}", style(format!("⚠️  Skipping {}: Failed to merge pipeline config", kit)).yellow());
-                continue;
-            }
-            
-            // Extract meta information
-            let mut meta_output = Command::new("spruce")
-                .args(&["merge", "--skip-eval", "--cherry-pick", "meta"])
-                .arg("-")
-                .stdin(std::process::Stdio::piped())
-                .stdout(std::process::Stdio::piped())
-                .spawn()
-                .context("Failed to spawn meta command")?;
-
-            {
-                let mut stdin = meta_output.stdin.take().unwrap();
-                use std::io::Write;
-                stdin.write_all(&merged_config.stdout)?;
-            }
+    pub async fn view_ci_status(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        if !options.format.is_table() {
+            // Machine-readable output shouldn't carry terminal styling
+            console::set_colors_enabled(false);
+        }
+        if options.refresh > 0 {
+            return self.watch_ci_status(options, fly).await;
+        }
 
-            let meta_result = meta_output.wait_with_output().context("Failed to get meta output")?;
-            let meta: PipelineMeta = if meta_result.status.success() {
-                #[derive(Deserialize)]
-                struct MetaWrapper { meta: PipelineMeta }
-                let wrapper: MetaWrapper = serde_yaml::from_str(&String::from_utf8(meta_result.stdout)?)?;
-                wrapper.meta
-            } else {
-                continue;
-            };
-            
-            // Get pipeline name from meta
-            let pipeline_name = meta.pipeline
-                .or(meta.name)
-                .unwrap_or_else(|| format!("genesis-kit-{}", kit));
-            
-            // Now fetch the build status using the correct pipeline name
-            let output = AsyncCommand::new("fly")
-                .args(["builds", "-j", &format!("{}/test-kit", pipeline_name)])
-                .output()
-                .await
-                .context("Failed to fetch build status")?;
-
-            let status = if output.status.success() {
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                let latest_status = stdout.lines().next()
-                    .map(|line| line.split_whitespace().nth(2))
-                    .flatten()
-                    .unwrap_or("unknown");
-
-                match latest_status {
-                    "succeeded" => style("Passing").green().to_string(),
-                    "failed" => style("Failed").red().to_string(),
-                    "started" => style("Running").yellow().to_string(),
-                    _ => style("Unknown").dim().to_string(),
-                }
-            } else {
-                style("Error").red().to_string()
-            };
+        if options.format.is_table() {
+            say!("\n{}", heading("📊 CI STATUS"));
+        }
+        let kits = exclude_kits(
+            apply_kit_filter(discover_kits()?, options.kit_filter.as_ref())?,
+            &options.exclude_kits,
+        )?;
+        let statuses = collect_statuses(&kits, fly, options, &self.multi_progress).await?;
+        println!("{}", render_statuses(statuses, options)?);
+        Ok(())
+    }
 
-            // Fetch pipeline config for version info
-            let config_output = AsyncCommand::new("fly")
-                .args(["configure", "-t", "genesis-kits", "-j", kit, "--json"])
-                .output()
-                .await
-                .context("Failed to fetch pipeline config")?;
+    /// `--refresh`: redraws the status in place every N seconds until Ctrl-C,
+    /// which the global handler turns into a clean exit with the cursor restored.
+    async fn watch_ci_status(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        let kits = exclude_kits(
+            apply_kit_filter(discover_kits()?, options.kit_filter.as_ref())?,
+            &options.exclude_kits,
+        )?;
+        let mut ticks = tokio::time::interval(std::time::Duration::from_secs(options.refresh));
+        self.term.hide_cursor()?;
 
-            let config: Value = if config_output.status.success() {
-                serde_json::from_slice(&config_output.stdout)
-                    .context("Failed to parse pipeline config")?
-            } else {
-                Value::Null
+        loop {
+            ticks.tick().await;
+            let rendered = match collect_statuses(&kits, fly, options, &self.multi_progress).await {
+                Ok(statuses) => render_statuses(statuses, options)?,
+                Err(e) => error_line(&format!("{:#}", e)),
             };
 
-            let version = config["version"]
-                .as_str()
-                .unwrap_or("unknown")
-                .to_string();
+            self.term.clear_screen()?;
+            if options.format.is_table() {
+                say!("{}", heading("📊 CI STATUS"));
+            }
+            println!("{}", rendered);
+            println!("\n{}", style(format!(
+                "Updated {}; refreshing every {}s (Ctrl-C to quit)",
+                chrono::Local::now().format("%H:%M:%S"), options.refresh)).dim());
+        }
+    }
 
-            let template_version = config["template_version"]
-                .as_str()
-                .unwrap_or("unknown")
-                .to_string();
+    pub async fn update_ci_config(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        check_extra_args(&options.fly_args, SET_PIPELINE_FLAGS)?;
+        let kit = self.select_kit(options.kit.as_deref(), "Select kit to configure")?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let pipeline = match &options.pipeline {
+            Some(pipeline) => pipeline.clone(),
+            None => kit.pipeline_name()?,
+        };
+        say!("\n{}", heading("🔄 UPDATING CI CONFIGURATION"));
 
-            statuses.push(KitStatus {
-                name: (*kit).into(),
-                version,
-                template_version,
-                ci_status: status,
-            });
+        let pb = create_progress_bar(&self.multi_progress, 1, "Downloading current pipeline config...");
+        let output = fly.get_pipeline(&pipeline)
+            .await
+            .context("Failed to fetch pipeline config")?;
+
+        if !output.success {
+            return Err(fly_error(fly, "download the configuration of", &pipeline, &output.stderr));
         }
+        let current = output.stdout_lossy();
+        pb.inc(1);
+        pb.finish_with_message("✓ Current pipeline config downloaded");
 
-        let status_table = Table::new(statuses).to_string();
-        println!("{}", status_table);
-        Ok(())
+        // Either take the user-supplied file or let them edit a copy of the live config
+        let (updated, updated_label) = match &options.file {
+            Some(path) => (
+                std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+                path.display().to_string(),
+            ),
+            None => (edit_in_editor(&current, &kit.name)?, String::from("edited")),
+        };
+
+        self.upload_pipeline(options, fly, &pipeline, &current, &updated, &updated_label).await
+    }
+
+    /// Uploads `--file` as the kit's pipeline as-is, without a spruce merge.
+    pub async fn set_ci_pipeline(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        check_extra_args(&options.fly_args, SET_PIPELINE_FLAGS)?;
+        let kit = self.select_kit(options.kit.as_deref(), "Select kit to set")?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let path = options.file.as_ref().context("--file is required")?;
+        let updated = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        spruce::parse_documents(&updated)
+            .with_context(|| format!("{} is not valid YAML", path.display()))?;
+
+        let pipeline = match &options.pipeline {
+            Some(pipeline) => pipeline.clone(),
+            None => kit.pipeline_name()?,
+        };
+        say!("\n{}", heading(&format!("📤 SETTING {}", pipeline)));
+
+        let current = live_pipeline(fly, &pipeline).await?;
+        self.upload_pipeline(options, fly, &pipeline, &current, &updated, &path.display().to_string()).await
     }
 
-    async fn update_ci_config(&self) -> Result<()> {
-        let kit = Select::with_theme(&self.theme)
-            .with_prompt(&param("Select kit to configure"))
-            .items(AVAILABLE_KITS)
-            .interact()?;
+    /// `gk ci diff-config`: diffs the kit's locally merged pipeline against the live one.
+    pub async fn diff_ci_config(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        let kit = self.select_kit(options.kit.as_deref(), "Select kit to diff")?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let pipeline = match &options.pipeline {
+            Some(pipeline) => pipeline.clone(),
+            None => kit.pipeline_name()?,
+        };
+        say!("\n{}", heading(&format!("🔍 LOCAL VS LIVE {}", pipeline)));
 
-        let kit_name = AVAILABLE_KITS[kit];
-        println!("\n{}", heading("🔄 UPDATING CI CONFIGURATION"));
+        let ci_dir = kit.path.clone();
+        let run_scripts = options.run_scripts;
+        let repipe_options = RepipeOptions {
+            env: options.env.clone(),
+            settings: options.settings.clone(),
+            target: options.target.clone(),
+            ..Default::default()
+        };
+        let local = tokio::task::spawn_blocking(move || {
+            RepipeCommand::in_ci_dir(repipe_options, ci_dir)?.merged_config(run_scripts)
+        }).await??;
+        let live = live_pipeline(fly, &pipeline).await?;
 
-        let pb = create_progress_bar(&self.multi_progress, 3, "Updating CI config");
+        match render_diff(&live, &local, &format!("{} (live)", pipeline), "local merge") {
+            Some(diff) => println!("{}", diff),
+            None => println!("{}", info("No drift: the live pipeline matches the local merge")),
+        }
+        Ok(())
+    }
+
+    /// `gk ci configure --set-var`: patches single values in the live pipeline
+    /// through a spruce overlay instead of rebuilding it like repipe does.
+    pub async fn configure_ci_vars(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        check_extra_args(&options.fly_args, SET_PIPELINE_FLAGS)?;
+        if options.set_vars.is_empty() {
+            bail!("Nothing to change; pass --set-var NAME=VALUE");
+        }
+        let kit = self.select_kit(options.kit.as_deref(), "Select kit to configure")?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let pipeline = match &options.pipeline {
+            Some(pipeline) => pipeline.clone(),
+            None => kit.pipeline_name()?,
+        };
+        say!("\n{}", heading(&format!("🎛️  CONFIGURING {}", pipeline)));
 
-        // Download current pipeline config
-        pb.set_message("Downloading current pipeline config...");
-        let output = AsyncCommand::new("fly")
-            .args([
-                "get-pipeline",
-                "-t", "genesis-kits",
-                "-p", kit_name,
-            ])
-            .output()
+        let output = fly.get_pipeline(&pipeline)
             .await
             .context("Failed to fetch pipeline config")?;
+        if !output.success {
+            return Err(fly_error(fly, "download the configuration of", &pipeline, &output.stderr));
+        }
+        let current = output.stdout_lossy();
+        let live: serde_yaml::Value = serde_yaml::from_str(&current)
+            .context("Failed to parse the live pipeline config")?;
 
-        if !output.status.success() {
-            anyhow::bail!("Failed to download pipeline configuration");
+        // The live config first, then one overlay per --set-var, in order
+        let mut layers = vec![yaml_tempfile(&pipeline, &current)?];
+        for (name, value) in &options.set_vars {
+            let overlay = var_overlay(Some(&live), &name.split('.').collect::<Vec<_>>(), parse_var_value(value))
+                .with_context(|| format!("Can't set '{}'", name))?;
+            layers.push(yaml_tempfile(&pipeline, &serde_yaml::to_string(&overlay)?)?);
         }
-        pb.inc(1);
+        let paths: Vec<PathBuf> = layers.iter().map(|layer| layer.path().to_path_buf()).collect();
+        let updated = tokio::task::spawn_blocking(move || spruce::overlay(&paths)).await??;
 
-        // Save to temporary file
-        let config = String::from_utf8_lossy(&output.stdout);
-        let temp_file = format!("/tmp/{}-pipeline.yml", kit_name);
-        std::fs::write(&temp_file, config.as_bytes())
-            .context("Failed to save pipeline config")?;
-        pb.inc(1);
+        self.upload_pipeline(options, fly, &pipeline, &current, &updated, "with --set-var").await
+    }
+
+    /// Shows what would change, then (unless `--dry-run` or declined) uploads `updated`.
+    async fn upload_pipeline(&self, options: &CiOptions, fly: &dyn FlyClient, pipeline: &str,
+                             current: &str, updated: &str, updated_label: &str) -> Result<()> {
+        let Some(diff) = render_diff(current, updated, "live", updated_label) else {
+            println!("{}", style("No changes to upload").dim());
+            return Ok(());
+        };
+        say!("\n{}", heading("📝 PIPELINE CHANGES"));
+        println!("{}", diff);
+
+        if !options.dry_run {
+            require_terminal("--dry-run to preview without uploading")?;
+            let confirmed = Confirm::with_theme(&self.theme)
+                .with_prompt(param("Upload the updated configuration?"))
+                .default(false)
+                .interact()
+                .map_err(|e| prompt_error(e, "--dry-run to preview without uploading"))?;
+            if !confirmed {
+                println!("{}", warning("Aborted: pipeline not updated"));
+                return Ok(());
+            }
+        }
 
-        // Set updated pipeline
-        pb.set_message("Uploading new configuration...");
-        let set_output = AsyncCommand::new("fly")
-            .args([
-                "set-pipeline",
-                "-t", "genesis-kits",
-                "-p", kit_name,
-                "-c", &temp_file,
-                "--non-interactive",
-            ])
-            .output()
+        let upload = yaml_tempfile(pipeline, updated)?;
+
+        // The bar stays off under --dry-run, which prints the fly command instead
+        let pb = if options.dry_run {
+            ProgressBar::hidden()
+        } else {
+            create_progress_bar(&self.multi_progress, 1, "Uploading new configuration...")
+        };
+        let set_output = fly.set_pipeline(pipeline, upload.path(), &options.fly_args)
             .await
             .context("Failed to update pipeline")?;
 
-        if !set_output.status.success() {
-            anyhow::bail!("Failed to update pipeline configuration");
+        if !set_output.success {
+            return Err(fly_error(fly, "update the configuration of", pipeline, &set_output.stderr));
         }
 
-        pb.finish_with_message("✓ CI configuration updated");
+        if options.dry_run {
+            println!("{}", warning("Dry run: pipeline not updated"));
+        } else {
+            pb.inc(1);
+            pb.finish_with_message("✓ CI configuration updated");
+        }
         Ok(())
     }
 
-    async fn trigger_ci_build(&self) -> Result<()> {
-        let kit = Select::with_theme(&self.theme)
-            .with_prompt(&param("Select kit to build"))
-            .items(AVAILABLE_KITS)
-            .interact()?;
+    pub async fn set_pipeline_paused(&self, options: &CiOptions, fly: &dyn FlyClient, paused: bool) -> Result<()> {
+        let (verb, state) = if paused { ("pause", "paused") } else { ("unpause", "unpaused") };
+        let kit = self.select_kit(options.kit.as_deref(), &format!("Select kit to {}", verb))?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let pipeline = kit.pipeline_name()?;
 
-        let kit_name = AVAILABLE_KITS[kit];
-        println!("\n{}", style("🚀 Triggering CI build...").cyan().bold());
+        let output = fly.set_paused(&pipeline, paused)
+            .await
+            .with_context(|| format!("Failed to {} pipeline", verb))?;
 
-        let output = AsyncCommand::new("fly")
-            .args([
-                "trigger-job",
-                "-t", "genesis-kits",
-                "-j", &format!("{}/test-kit", kit_name),
-                "--watch",
-            ])
-            .output()
+        if !output.success {
+            return Err(fly_error(fly, &format!("{} pipeline", verb), &pipeline, &output.stderr));
+        }
+
+        if !options.dry_run {
+            println!("{}", success(&format!("Pipeline {} is now {}", pipeline, state)));
+        }
+        Ok(())
+    }
+
+    pub async fn set_pipeline_exposed(&self, options: &CiOptions, fly: &dyn FlyClient, exposed: bool) -> Result<()> {
+        let (verb, state) = if exposed { ("expose", "public") } else { ("hide", "private") };
+        let kit = self.select_kit(options.kit.as_deref(), &format!("Select kit to {}", verb))?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let pipeline = kit.pipeline_name()?;
+
+        let output = fly.set_exposed(&pipeline, exposed)
             .await
-            .context("Failed to trigger build")?;
+            .with_context(|| format!("Failed to {} pipeline", verb))?;
 
-        if output.status.success() {
-            println!("{}", style("✓ Build completed successfully!").green());
+        if !output.success {
+            return Err(fly_error(fly, &format!("{} pipeline", verb), &pipeline, &output.stderr));
+        }
+
+        if !options.dry_run {
+            println!("{}", success(&format!("Pipeline {} is now {}", pipeline, state)));
+        }
+        Ok(())
+    }
+
+    pub async fn list_ci_jobs(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        let kit = self.select_kit(options.kit.as_deref(), "Select kit to list jobs for")?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let pipeline = kit.pipeline_name()?;
+        if options.format.is_table() {
+            say!("\n{}", heading(&format!("📋 JOBS IN {}", pipeline)));
         } else {
-            println!("{}", style("⨯ Build failed").red());
-            println!("Build output:\n{}", String::from_utf8_lossy(&output.stderr));
+            console::set_colors_enabled(false);
+        }
+
+        let output = fly.jobs(&pipeline)
+            .await
+            .context("Failed to list jobs")?;
+
+        if !output.success {
+            return Err(fly_error(fly, "list jobs for", &pipeline, &output.stderr));
+        }
+
+        let jobs: Value = parse_fly_json(&output.stdout)
+            .context("Failed to parse job list")?;
+        let rows: Vec<JobStatus> = jobs.as_array()
+            .map(|jobs| jobs.iter().map(|job| JobStatus {
+                name: job["name"].as_str().unwrap_or("unknown").to_string(),
+                paused: if job["paused"].as_bool().unwrap_or(false) {
+                    style("yes").fg(palette().warning).to_string()
+                } else {
+                    String::from("no")
+                },
+                next_build: job["next_build"]["status"]
+                    .as_str()
+                    .map(String::from)
+                    .unwrap_or_else(|| style("none").dim().to_string()),
+            }).collect())
+            .unwrap_or_default();
+
+        if let Some(output) = options.format.serialize(&rows)? {
+            println!("{}", output);
+        } else if rows.is_empty() {
+            println!("{}", style("No jobs found").dim());
+        } else {
+            println!("{}", Table::new(rows));
         }
         Ok(())
     }
 
-    async fn view_ci_logs(&self) -> Result<()> {
-        let kit = Select::with_theme(&self.theme)
-            .with_prompt(&param("Select kit to view logs"))
-            .items(AVAILABLE_KITS)
-            .interact()?;
-
-        let kit_name = AVAILABLE_KITS[kit];
-        println!("\n{}", heading("📜 RECENT CI LOGS"));
-        println!("{}", style("Fetching latest CI logs...").dim());
-
-        let output = AsyncCommand::new("fly")
-            .args([
-                "builds",
-                "-t", "genesis-kits",
-                "-j", &format!("{}/test-kit", kit_name),
-                "--count=1",
-                "--json",
-            ])
-            .output()
+    pub async fn trigger_ci_build(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        check_extra_args(&options.fly_args, TRIGGER_JOB_FLAGS)?;
+        if options.all {
+            return self.trigger_all_builds(options, fly).await;
+        }
+        let kit = self.select_kit(options.kit.as_deref(), "Select kit to build")?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let pipeline = kit.pipeline_name()?;
+        let job = resolve_job_ref(&kit.name, &pipeline);
+
+        if options.check_resources {
+            warn_unchecked_resources(fly, &pipeline).await?;
+        }
+
+        if !options.force {
+            if let Some(build) = latest_build(fly, &job).await? {
+                if is_build_active(&build) {
+                    let number = build_number(&build).unwrap_or("?");
+                    let hint = "--force to trigger a duplicate build";
+                    if require_terminal(hint).is_err() {
+                        return Err(CiError::BuildInProgress { job, build: number.to_string() }.into());
+                    }
+                    let trigger_anyway = Confirm::with_theme(&self.theme)
+                        .with_prompt(param(&format!("Build #{} of {} is already running — trigger anyway?", number, job)))
+                        .default(false)
+                        .interact()
+                        .map_err(|e| prompt_error(e, hint))?;
+                    if !trigger_anyway {
+                        println!("{}", warning("Not triggering a duplicate build"));
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        say!("\n{}", style("🚀 Triggering CI build...").fg(palette().info).bold());
+
+        let output = fly.trigger_live(&job, options.tail, &options.fly_args)
             .await
-            .context("Failed to fetch build info")?;
+            .context("Failed to trigger build")?;
 
-        if !output.status.success() {
-            anyhow::bail!("Failed to fetch build information");
-        }
-
-        let builds: Value = serde_json::from_slice(&output.stdout)
-            .context("Failed to parse build info")?;
-
-        if let Some(build) = builds.as_array().and_then(|arr| arr.first()) {
-            if let Some(build_id) = build["number"].as_str() {
-                let log_output = AsyncCommand::new("fly")
-                    .args([
-                        "watch",
-                        "-t", "genesis-kits",
-                        "-j", &format!("{}/test-kit", kit_name),
-                        "-b", build_id,
-                    ])
-                    .output()
+        if options.dry_run {
+            return Ok(());
+        }
+        if finish_stream(fly, &output)? {
+            println!("{}", success("Build completed successfully!"));
+        } else {
+            println!("{}", error_line("Build failed"));
+        }
+        Ok(())
+    }
+
+    /// `trigger --all`: triggers every discovered kit's job, up to `--concurrency`
+    /// at once and without watching, then tabulates the build numbers. Fails
+    /// at the end when any kit couldn't be triggered.
+    async fn trigger_all_builds(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        let kits = exclude_kits(
+            apply_kit_filter(discover_kits()?, options.kit_filter.as_ref())?,
+            &options.exclude_kits,
+        )?;
+        say!("\n{}", heading("🚀 TRIGGERING ALL KITS"));
+
+        let bar = create_progress_bar(&self.multi_progress, kits.len() as u64, "Triggering builds...");
+        let bar = &bar;
+        let outcomes: Vec<TriggerOutcome> = stream::iter(&kits)
+            .map(|kit| async move {
+                let outcome = trigger_kit(kit, fly, options).await;
+                bar.inc(1);
+                outcome
+            })
+            .buffered(options.status_concurrency())
+            .collect()
+            .await;
+        bar.finish_and_clear();
+
+        let failed = outcomes.iter().filter(|o| o.failed).count();
+        println!("{}", Table::new(&outcomes));
+        if failed > 0 {
+            bail!("{} of {} kits could not be triggered", failed, outcomes.len());
+        }
+        Ok(())
+    }
+
+    /// Attaches to the kit's running build, or reports how the last one ended.
+    pub async fn watch_ci_build(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        let kit = self.select_kit(options.kit.as_deref(), "Select kit to watch")?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let job = resolve_job_ref(&kit.name, &kit.pipeline_name()?);
+
+        let Some(build) = latest_build(fly, &job).await? else {
+            println!("{}", style(format!("No builds yet for {}", job)).dim());
+            return Ok(());
+        };
+        let build_id = build_number(&build).context("Build has no number")?;
+        let status = build["status"].as_str().unwrap_or("unknown");
+
+        if is_build_active(&build) {
+            println!("{} {} #{}\n", info("Watching"), command(&job), build_id);
+            let output = fly.watch_live(&job, build_id, options.tail).await?;
+            if !finish_stream(fly, &output)? {
+                bail!("Build #{} of {} did not succeed", build_id, job);
+            }
+            return Ok(());
+        }
+
+        println!("{} #{} of {} finished: {}", info("Build"), build_id, job, style(status).bold());
+        let hint = "`gk ci logs` to read them";
+        let show_logs = require_terminal(hint).is_ok() && Confirm::with_theme(&self.theme)
+            .with_prompt(param("Show its logs?"))
+            .default(false)
+            .interact()
+            .map_err(|e| prompt_error(e, hint))?;
+        if show_logs {
+            let log_output = fly.watch_live(&job, build_id, options.tail)
+                .await
+                .context("Failed to fetch build logs")?;
+            finish_stream(fly, &log_output)?;
+        }
+        Ok(())
+    }
+
+    pub async fn view_ci_logs(&self, options: &CiOptions, fly: &dyn FlyClient) -> Result<()> {
+        let kit = self.select_kit(options.kit.as_deref(), "Select kit to view logs")?;
+        let fly = kit_fly(&kit, options, fly);
+        let fly = fly.as_ref();
+        let kit_name = kit.name.as_str();
+        let job = resolve_job_ref(kit_name, &kit.pipeline_name()?);
+
+        if let Some(build) = options.build {
+            say!("\n{}", heading(&format!("📜 CI LOGS FOR BUILD #{}", build)));
+            let log_output = fly.watch_live(&job, &build.to_string(), options.tail)
+                .await
+                .context("Failed to fetch build logs")?;
+            if !log_output.success && is_not_found(&log_output.stderr_lossy()) {
+                bail!("Build #{} of {} not found", build, job);
+            }
+            finish_stream(fly, &log_output)?;
+            return Ok(());
+        }
+
+        say!("\n{}", heading("📜 RECENT CI LOGS"));
+        say!("{}", style("Fetching latest CI logs...").dim());
+        if let Some(build) = latest_build(fly, &job).await? {
+            if let Some(build_id) = build_number(&build) {
+                let log_output = fly.watch_live(&job, build_id, options.tail)
                     .await
                     .context("Failed to fetch build logs")?;
-
-                println!("{}", String::from_utf8_lossy(&log_output.stdout));
+                finish_stream(fly, &log_output)?;
             }
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// A `--set-var` value as YAML, so numbers and booleans keep their type; anything
+/// that doesn't parse is taken as a plain string.
+fn parse_var_value(raw: &str) -> serde_yaml::Value {
+    serde_yaml::from_str(raw).unwrap_or_else(|_| serde_yaml::Value::String(raw.to_string()))
+}
+
+/// The overlay that sets the dotted `path` to `value`. Where `live` holds a list
+/// the segment names the entry to change (e.g. `resources.git.source.branch`),
+/// which spruce then merges by `name`.
+fn var_overlay(live: Option<&serde_yaml::Value>, path: &[&str], value: serde_yaml::Value) -> Result<serde_yaml::Value> {
+    use serde_yaml::{Mapping, Value as Yaml};
+
+    let Some((segment, rest)) = path.split_first() else {
+        return Ok(value);
+    };
+    if let Some(Yaml::Sequence(entries)) = live {
+        let entry = entries.iter()
+            .find(|entry| entry.get("name").and_then(Yaml::as_str) == Some(*segment))
+            .with_context(|| format!("No entry named '{}' in the list", segment))?;
+        if rest.is_empty() {
+            bail!("'{}' is a whole list entry; name a key inside it", segment);
+        }
+        let Yaml::Mapping(mut patched) = var_overlay(Some(entry), rest, value)? else {
+            unreachable!("a non-empty path always yields a mapping");
+        };
+        patched.insert(Yaml::from("name"), Yaml::from(*segment));
+        return Ok(Yaml::Sequence(vec![Yaml::Mapping(patched)]));
+    }
+
+    let child = var_overlay(live.and_then(|node| node.get(*segment)), rest, value)?;
+    let mut overlay = Mapping::new();
+    overlay.insert(Yaml::from(*segment), child);
+    Ok(Yaml::Mapping(overlay))
+}
+
+/// Writes `content` to a temporary `.yml` file that lives as long as the handle.
+fn yaml_tempfile(prefix: &str, content: &str) -> Result<tempfile::NamedTempFile> {
+    let mut file = tempfile::Builder::new()
+        .prefix(&format!("{}-pipeline-", prefix))
+        .suffix(".yml")
+        .tempfile()
+        .context("Failed to create temporary pipeline file")?;
+    {
+        use std::io::Write;
+        file.write_all(content.as_bytes())
+            .context("Failed to save pipeline config")?;
+    }
+    Ok(file)
+}
+
+/// Opens `content` in `$VISUAL`/`$EDITOR` (falling back to `vi`) and returns the saved result.
+fn edit_in_editor(content: &str, kit: &str) -> Result<String> {
+    let mut file = tempfile::Builder::new()
+        .prefix(&format!("{}-pipeline-", kit))
+        .suffix(".yml")
+        .tempfile()
+        .context("Failed to create temporary pipeline file")?;
+    {
+        use std::io::Write;
+        file.write_all(content.as_bytes())?;
+    }
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().context("Empty $EDITOR")?;
+
+    let status = Command::new(program)
+        .args(parts)
+        .arg(file.path())
+        .run_status()
+        .with_context(|| format!("Failed to launch editor '{}'", editor))?;
+    if !status.success() {
+        bail!("Editor '{}' exited with {}", editor, status);
+    }
+
+    std::fs::read_to_string(file.path()).context("Failed to read edited pipeline config")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fly::MockFlyClient;
+
+    fn plain(text: &str) -> String {
+        console::strip_ansi_codes(text).into_owned()
+    }
+
+    /// Kits named `names` under a scratch directory, each with an empty `ci/`.
+    fn scratch_kits(names: &[&str]) -> (tempfile::TempDir, Vec<Kit>) {
+        let root = tempfile::tempdir().unwrap();
+        let kits = names.iter()
+            .map(|name| {
+                let dir = root.path().join(name);
+                std::fs::create_dir_all(dir.join("ci")).unwrap();
+                Kit::load(&dir).unwrap()
+            })
+            .collect();
+        (root, kits)
+    }
+
+    fn job(kit: &str) -> String {
+        resolve_job_ref(kit, &conventional_pipeline_name(kit))
+    }
+
+    fn status_fly(kits: &[Kit]) -> MockFlyClient {
+        let started = Utc::now().timestamp() - 120;
+        let mut fly = MockFlyClient::new("ci");
+        let names: Vec<Value> = kits.iter()
+            .map(|kit| serde_json::json!({ "name": conventional_pipeline_name(&kit.name) }))
+            .collect();
+        fly.pipelines = Some(MockFlyClient::ok(&Value::Array(names).to_string()));
+        for (kit, status) in [("alpha", "succeeded"), ("beta", "failed"), ("gamma", "started")] {
+            let builds = format!(r#"[{{"id": 7, "name": "12", "status": "{}", "start_time": {}}}]"#, status, started);
+            fly.builds.insert(job(kit), MockFlyClient::ok(&builds));
+        }
+        fly.configure.insert(String::from("alpha"), MockFlyClient::ok(r#"{"version": "1.0.0", "template_version": "1.2.0"}"#));
+        fly.configure.insert(String::from("beta"), MockFlyClient::ok(r#"{"version": "2.0.0", "template_version": "2.0.0"}"#));
+        fly
+    }
+
+    fn status_options() -> CiOptions {
+        CiOptions { no_spruce: true, cache_ttl: 0, concurrency: 1, ..Default::default() }
+    }
+
+    #[test]
+    fn check_auth_flags_only_auth_failures() {
+        let fly = MockFlyClient::new("prod");
+        let cases = [
+            ("error: not authorized. run the following to log in:", true),
+            ("Please login again with `fly -t prod login`", true),
+            ("error: token expired", true),
+            ("error: pipeline not found", false),
+            ("", false),
+        ];
+        for (stderr, rejected) in cases {
+            match check_auth(&fly, stderr.as_bytes()) {
+                Ok(()) => assert!(!rejected, "{:?} passed through", stderr),
+                Err(e) => {
+                    assert!(rejected, "{:?} was taken for an auth failure", stderr);
+                    assert!(matches!(e.downcast_ref::<CiError>(),
+                        Some(CiError::NotAuthenticated { target }) if target == "prod"));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fly_error_passes_unrelated_failures_through() {
+        let fly = MockFlyClient::new("prod");
+        let e = fly_error(&fly, "pause", "kit", b"error: pipeline not found");
+        assert!(e.downcast_ref::<CiError>().is_none());
+        assert_eq!(e.to_string(), "Pipeline 'kit' not found on target prod. Has it been set with 'gk repipe'?");
+        let e = fly_error(&fly, "pause", "kit", b"connection refused");
+        assert_eq!(e.to_string(), "Failed to pause 'kit': connection refused");
+    }
+
+    #[tokio::test]
+    async fn collect_statuses_maps_fly_output_to_rows() {
+        let (_root, kits) = scratch_kits(&["alpha", "beta", "gamma", "delta"]);
+        let fly = status_fly(&kits);
+        let progress = MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden());
+
+        let statuses = collect_statuses(&kits, &fly, &status_options(), &progress).await.unwrap();
+        let rows: Vec<[String; 5]> = statuses.iter()
+            .map(|s| [&s.name, &s.ci_status, &s.last_build, &s.version, &s.drift].map(String::clone))
+            .collect();
+        assert_eq!(rows, [
+            ["alpha", "Passing", "2m ago", "1.0.0", "behind template"],
+            ["beta", "Failed", "2m ago", "2.0.0", "current"],
+            ["gamma", "Running", "2m ago", "unknown", "-"],
+            ["delta", "Not Deployed", "never", "unknown", "-"],
+        ].map(|row| row.map(String::from)));
+    }
+
+    #[tokio::test]
+    async fn missing_pipeline_or_job_is_not_deployed() {
+        let (_root, kits) = scratch_kits(&["alpha"]);
+        let cases = [
+            ("error: pipeline not found", "Not Deployed", "never"),
+            ("error: job 'test-kit' not found", "Not Deployed", "never"),
+            ("error: Get \"https://ci\": dial tcp: connection refused", "Error", "unknown"),
+        ];
+        let mut statuses = Vec::new();
+        for (stderr, ci_status, last_build) in cases {
+            let mut fly = MockFlyClient::new("ci");
+            fly.builds.insert(job("alpha"), MockFlyClient::failed(stderr));
+            let status = fetch_kit_status(&kits[0], &job("alpha"), &fly).await.unwrap();
+            assert_eq!((status.ci_status.as_str(), status.last_build.as_str()), (ci_status, last_build), "{}", stderr);
+            statuses.push(status);
+        }
+
+        console::set_colors_enabled(true);
+        let styled: Vec<String> = statuses.into_iter().map(|s| styled_status(s).ci_status).collect();
+        assert_eq!(styled[0], style("Not Deployed").dim().to_string());
+        assert_eq!(styled[2], style("Error").fg(palette().error).to_string());
+    }
+
+    #[tokio::test]
+    async fn pipeline_prefix_reaches_every_fly_call() {
+        crate::kits::test_kit_root();
+        crate::kits::TEST_PIPELINE_PREFIX.with(|prefix| *prefix.borrow_mut() = Some(String::from("pr-")));
+        let pipeline = "pr-genesis-kit-alpha";
+        let mut fly = MockFlyClient::new("ci");
+        fly.pipelines = Some(MockFlyClient::ok(&serde_json::json!([{ "name": pipeline }]).to_string()));
+        fly.pipelines_config.insert(pipeline.to_string(), MockFlyClient::ok("jobs: []\n"));
+        let update = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(update.path(), "jobs:\n- name: test-kit\n").unwrap();
+        let options = CiOptions {
+            kit: Some(String::from("alpha")),
+            target: Some(String::from("ci")),
+            team: Some(String::from("main")),
+            file: Some(update.path().to_path_buf()),
+            dry_run: true,
+            force: true,
+            ..status_options()
+        };
+        let ui = GenesisKitUI::new();
+        let progress = MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden());
+
+        let kits = discover_kits().unwrap();
+        let alpha: Vec<Kit> = kits.into_iter().filter(|kit| kit.name == "alpha").collect();
+        collect_statuses(&alpha, &fly, &options, &progress).await.unwrap();
+        ui.set_pipeline_paused(&options, &fly, true).await.unwrap();
+        ui.trigger_ci_build(&options, &fly).await.unwrap();
+        ui.update_ci_config(&options, &fly).await.unwrap();
+        crate::kits::TEST_PIPELINE_PREFIX.with(|prefix| prefix.borrow_mut().take());
+
+        let job = format!("{}/test-kit", pipeline);
+        assert_eq!(*fly.pipeline_calls.lock().unwrap(), [
+            format!("builds {}", job),
+            format!("pause-pipeline {}", pipeline),
+            format!("trigger-job {}", job),
+            format!("get-pipeline {}", pipeline),
+            format!("set-pipeline {}", pipeline),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn concurrency_one_fetches_kits_in_order() {
+        let names = ["delta", "alpha", "gamma", "beta"];
+        let (_root, kits) = scratch_kits(&names);
+        let fly = status_fly(&kits);
+        let progress = MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden());
+
+        let statuses = collect_statuses(&kits, &fly, &status_options(), &progress).await.unwrap();
+        assert_eq!(statuses.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), names);
+        assert_eq!(*fly.builds_calls.lock().unwrap(), names.map(job));
+        assert_eq!(CiOptions::default().status_concurrency(), DEFAULT_STATUS_CONCURRENCY);
+    }
+
+    #[tokio::test]
+    async fn rendered_status_table_hides_last_build_unless_wide() {
+        let (_root, kits) = scratch_kits(&["alpha"]);
+        let fly = status_fly(&kits);
+        let progress = MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden());
+        let statuses = collect_statuses(&kits, &fly, &status_options(), &progress).await.unwrap();
+
+        let narrow = plain(&render_statuses(statuses.clone(), &status_options()).unwrap());
+        for header in ["Kit Name", "Version", "Template Version", "CI Status", "Drift"] {
+            assert!(narrow.contains(header), "missing {} in\n{}", header, narrow);
+        }
+        assert!(!narrow.contains("Last Build"));
+
+        let wide = plain(&render_statuses(statuses, &CiOptions { wide: true, ..status_options() }).unwrap());
+        assert!(wide.contains("Last Build") && wide.contains("2m ago"));
+    }
+
+    #[test]
+    fn statuses_are_styled_only_when_rendered() {
+        console::set_colors_enabled(true);
+        let status = KitStatus {
+            name: String::from("bosh"),
+            version: String::from("1.0.0"),
+            template_version: String::from("1.2.0"),
+            ci_status: format!("Passing {}", CACHED_MARK),
+            last_build: String::from("never"),
+            drift: String::from(DRIFT_BEHIND),
+        };
+        let styled = styled_status(status.clone());
+        assert_ne!(styled.ci_status, status.ci_status);
+        assert_eq!(plain(&styled.ci_status), "Passing (cached)");
+        assert_eq!(plain(&styled.drift), "⚠ behind template");
+        assert_eq!(plain(&styled.name), "bosh");
+        assert!(!serde_json::to_string(&status).unwrap().contains('\u{1b}'));
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{bail, Result};
+use console::style;
+use serde::Serialize;
+use std::{env, process::Command};
+use crate::{
+    say,
+    ui::GenesisKitUI,
+    ui::styles::*,
+    ui::format::OutputFormat,
+    constants::CI_TARGET,
+    process::RunExt,
+    flyrc::FlyRc,
+    commands::repipe::{find_tool, RepipeCommand, FLY_DOWNLOAD_URL, REQUIRED_TOOLS},
+};
+
+/// One line of the doctor checklist. Soft checks warn but don't fail the run.
+#[derive(Serialize)]
+struct Check {
+    name: String,
+    #[serde(rename = "ok")]
+    passed: bool,
+    #[serde(rename = "required")]
+    hard: bool,
+    detail: String,
+}
+
+impl Check {
+    fn hard(name: impl Into<String>, result: Result<String>) -> Self {
+        Self::new(name, result, true)
+    }
+
+    fn soft(name: impl Into<String>, result: Result<String>) -> Self {
+        Self::new(name, result, false)
+    }
+
+    fn new(name: impl Into<String>, result: Result<String>, hard: bool) -> Self {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, e.to_string()),
+        };
+        Self { name: name.into(), passed, hard, detail }
+    }
+
+    fn print(&self) {
+        let mark = match (self.passed, self.hard) {
+            (true, _) => style("✓").fg(palette().success),
+            (false, true) => style("✗").fg(palette().error),
+            (false, false) => style("✗").fg(palette().warning),
+        };
+        println!("  {} {}", mark, self.name);
+        for line in self.detail.lines().filter(|l| !l.is_empty()) {
+            println!("      {}", style(line).dim());
+        }
+    }
+}
+
+/// Finds `cmd` and reports the first line of `cmd --version`.
+fn tool_version(cmd: &str, url: Option<&str>) -> Result<String> {
+    let path = find_tool(cmd, url)?;
+    let output = Command::new(&path).arg("--version").run_output()?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    let version = version.lines().next().unwrap_or_default().trim();
+    Ok(format!("{} ({})", version, path.display()))
+}
+
+fn check_flyrc() -> Result<String> {
+    let flyrc = FlyRc::load()?;
+    let targets = flyrc.target_names().count();
+    Ok(format!("{} ({} target{})", flyrc.path.display(), targets, if targets == 1 { "" } else { "s" }))
+}
+
+fn check_kit_repo() -> Result<String> {
+    RepipeCommand::find_ci_directory(false).map(|dir| dir.display().to_string())
+}
+
+/// `fly status` exits non-zero when the target's token is missing or expired.
+fn check_login(target: &str) -> Result<String> {
+    let output = Command::new("fly").args(["-t", target, "status"]).run_output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        bail!("Not logged in; run 'fly -t {} login'", target)
+    }
+}
+
+/// Fails when any required check did.
+fn check_required(checks: &[Check]) -> Result<()> {
+    let failed = checks.iter().filter(|c| c.hard && !c.passed).count();
+    if failed > 0 {
+        bail!("{} required check{} failed", failed, if failed == 1 { "" } else { "s" });
+    }
+    Ok(())
+}
+
+impl GenesisKitUI {
+    /// Runs every check and prints them as a checklist, or as `format` when
+    /// that's machine-readable. Fails when any required check does.
+    pub fn run_doctor(&self, format: OutputFormat) -> Result<()> {
+        if format.is_table() {
+            say!("\n{}\n", heading("🩺 ENVIRONMENT CHECK"));
+        }
+
+        let mut checks: Vec<Check> = REQUIRED_TOOLS.iter()
+            .map(|(cmd, url)| Check::hard(*cmd, tool_version(cmd, *url)))
+            .collect();
+        checks.push(Check::hard("fly", tool_version("fly", FLY_DOWNLOAD_URL)));
+        checks.push(Check::hard(".flyrc", check_flyrc()));
+        checks.push(Check::soft("Inside a kit repo", check_kit_repo()));
+
+        let mut targets = vec![CI_TARGET.to_string()];
+        if let Ok(target) = env::var("CONCOURSE_TARGET") {
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        if checks.iter().any(|c| c.name == "fly" && c.passed) {
+            for target in &targets {
+                checks.push(Check::soft(format!("Logged in to '{}'", target), check_login(target)));
+            }
+        }
+
+        if let Some(output) = format.serialize(&checks)? {
+            println!("{}", output);
+            return check_required(&checks);
+        }
+
+        for check in &checks {
+            check.print();
+        }
+
+        if let Err(e) = check_required(&checks) {
+            println!();
+            return Err(e);
+        }
+        println!("\n{}", info("All required checks passed"));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn json_lists_each_check_with_its_outcome() {
+        let checks = [
+            Check::hard("spruce", Ok(String::from("spruce v1.31.0"))),
+            Check::hard("fly", Err(anyhow::anyhow!("fly not found"))),
+            Check::soft("Inside a kit repo", Err(anyhow::anyhow!("No ci/ directory"))),
+        ];
+        let output = OutputFormat::Json.serialize(&checks).unwrap().unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&output).unwrap(), json!([
+            { "name": "spruce", "ok": true, "required": true, "detail": "spruce v1.31.0" },
+            { "name": "fly", "ok": false, "required": true, "detail": "fly not found" },
+            { "name": "Inside a kit repo", "ok": false, "required": false, "detail": "No ci/ directory" },
+        ]));
+
+        assert_eq!(check_required(&checks).unwrap_err().to_string(), "1 required check failed");
+        assert!(check_required(&checks[2..]).is_ok());
+    }
+}
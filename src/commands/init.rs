@@ -0,0 +1,148 @@
+use anyhow::{bail, Context, Result};
+use dialoguer::Input;
+use std::{env, fs, path::Path};
+use crate::{
+    say,
+    config,
+    constants::CI_TARGET,
+    ui::{self, GenesisKitUI, styles::*},
+};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+const BASE_TEMPLATE: &str = r#"---
+meta:
+  kit:    {kit}
+  name:   {pipeline}
+  target: {target}
+  team:   {team}
+
+  github:
+    owner:  genesis-community
+    repo:   (( concat meta.kit "-genesis-kit" ))
+    branch: main
+    uri:    (( concat "https://github.com/" meta.github.owner "/" meta.github.repo ))
+
+resources:
+  - name: git
+    type: git
+    source:
+      uri:    (( grab meta.github.uri ))
+      branch: (( grab meta.github.branch ))
+
+jobs:
+  - name: test-kit
+    public: true
+    serial: true
+    plan:
+      - get: git
+        trigger: true
+      - task: test-kit
+        config:
+          platform: linux
+          image_resource:
+            type: registry-image
+            source: { repository: busybox }
+          inputs:
+            - name: git
+          run:
+            path: sh
+            args: [-c, "echo 'No tests for {kit} yet'"]
+"#;
+
+const SETTINGS_TEMPLATE: &str = r#"---
+# Local settings, merged over pipeline/base.yml. Copy to settings-<env>.yml
+# (or settings/<env>.yml) for per-environment values.
+meta:
+  github:
+    owner: genesis-community
+"#;
+
+const BUILD_TEST_JOBS_TEMPLATE: &str = r#"#!/bin/bash
+# Writes pipeline/tests/<name>.yml job files for repipe to merge.
+# Replace with a real generator, or use `gk repipe --native-jobs`.
+set -eu
+exit 0
+"#;
+
+/// What `gk init` fills into the templates; unset fields are prompted for.
+#[derive(Debug, Default, Clone)]
+pub struct InitOptions {
+    pub kit: Option<String>,
+    pub target: Option<String>,
+    pub team: Option<String>,
+    /// Overwrite the template files in an existing `ci/`
+    pub force: bool,
+}
+
+/// `value`, else a prompt defaulting to `default`, else `default` itself.
+fn resolve(value: Option<String>, prompt: &str, default: String) -> Result<String> {
+    if let Some(value) = value {
+        return Ok(value);
+    }
+    if !ui::is_interactive() {
+        return Ok(default);
+    }
+    Ok(Input::<String>::with_theme(&dialoguer_theme())
+        .with_prompt(prompt)
+        .default(default)
+        .interact_text()?)
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+impl GenesisKitUI {
+    /// Scaffolds `ci/` in the current directory: a `pipeline/base.yml` that
+    /// merges and validates as is, a `settings.yml`, and a placeholder
+    /// `scripts/build-test-jobs`.
+    pub fn init_kit(&self, options: InitOptions) -> Result<()> {
+        let ci_dir = Path::new("ci");
+        if ci_dir.exists() && !options.force {
+            bail!("ci/ already exists; pass --force to overwrite its template files");
+        }
+
+        // The repo is usually named `<kit>-genesis-kit`
+        let dir_name = env::current_dir()?
+            .file_name()
+            .map(|n| n.to_string_lossy().trim_end_matches("-genesis-kit").to_string())
+            .unwrap_or_default();
+        let kit = resolve(options.kit, "Kit name", dir_name)?;
+        if kit.is_empty() {
+            bail!("A kit name is required; pass --kit");
+        }
+        let target = resolve(options.target, "Concourse target", CI_TARGET.to_string())?;
+        let team = resolve(options.team, "Concourse team", String::from("main"))?;
+
+        let base = BASE_TEMPLATE
+            .replace("{pipeline}", &config::config().pipeline_name(&kit))
+            .replace("{kit}", &kit)
+            .replace("{target}", &target)
+            .replace("{team}", &team);
+        let files = [
+            ("pipeline/base.yml", base.as_str()),
+            ("settings.yml", SETTINGS_TEMPLATE),
+            ("scripts/build-test-jobs", BUILD_TEST_JOBS_TEMPLATE),
+        ];
+        for (name, contents) in files {
+            write_file(&ci_dir.join(name), contents)?;
+        }
+        #[cfg(unix)]
+        {
+            let script = ci_dir.join("scripts").join("build-test-jobs");
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+                .with_context(|| format!("Failed to make {} executable", script.display()))?;
+        }
+
+        say!("\n{}", heading("🌱 KIT SCAFFOLDED"));
+        for (name, _) in files {
+            println!("{}", success(&format!("ci/{}", name)));
+        }
+        say!("\nNext: {}", command("gk repipe --validate"));
+        Ok(())
+    }
+}
@@ -1,3 +1,6 @@
 pub mod ci;
+pub mod doctor;
+pub mod init;
+pub mod native_jobs;
 pub mod template;
 pub mod repipe;
\ No newline at end of file
@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use log::info;
+use serde::Serialize;
+use std::{fs, path::Path};
+
+/// One generated job file: `jobs:` holding a single job.
+#[derive(Debug, Serialize)]
+struct JobFile {
+    jobs: Vec<Job>,
+}
+
+#[derive(Debug, Serialize)]
+struct Job {
+    name: String,
+    public: bool,
+    serial: bool,
+    plan: Vec<Step>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Step {
+    Get { get: String, trigger: bool },
+    Task { task: String, file: String },
+}
+
+/// The generated flavours, mirroring `build-test-jobs` and `build-upstream-jobs`.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Test,
+    Upstream,
+}
+
+impl Kind {
+    /// Where the specs live under `ci/`, which is also the output dir under `ci/pipeline/`.
+    fn dir(self) -> &'static str {
+        match self {
+            Kind::Test => "tests",
+            Kind::Upstream => "upstream",
+        }
+    }
+
+    fn job(self, name: &str) -> Job {
+        let (job_name, trigger_resource) = match self {
+            Kind::Test => (format!("test-{}", name), "git".to_string()),
+            Kind::Upstream => (format!("upstream-{}", name), name.to_string()),
+        };
+
+        let mut plan = vec![Step::Get { get: "git".to_string(), trigger: matches!(self, Kind::Test) }];
+        if let Kind::Upstream = self {
+            plan.push(Step::Get { get: trigger_resource, trigger: true });
+        }
+        plan.push(Step::Task {
+            task: job_name.clone(),
+            file: format!("git/ci/{}/{}/task.yml", self.dir(), name),
+        });
+
+        Job { name: job_name, public: true, serial: true, plan }
+    }
+}
+
+/// Names of the spec directories under `ci/<kind>/` that carry a `task.yml`.
+fn spec_names(base_dir: &Path, kind: Kind) -> Result<Vec<String>> {
+    let spec_dir = base_dir.join(kind.dir());
+    if !spec_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut names: Vec<String> = fs::read_dir(&spec_dir)
+        .with_context(|| format!("Failed to read {}", spec_dir.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.join("task.yml").is_file())
+        .filter_map(|path| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Writes `pipeline/tests/*.yml` and `pipeline/upstream/*.yml` for every
+/// `ci/tests/<name>/task.yml` and `ci/upstream/<name>/task.yml`, standing in
+/// for the kit's bash job-builder scripts.
+pub fn generate(base_dir: &Path) -> Result<()> {
+    for kind in [Kind::Test, Kind::Upstream] {
+        let names = spec_names(base_dir, kind)?;
+        if names.is_empty() {
+            continue;
+        }
+
+        let out_dir = base_dir.join("pipeline").join(kind.dir());
+        fs::create_dir_all(&out_dir)
+            .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+
+        for name in &names {
+            let file = JobFile { jobs: vec![kind.job(name)] };
+            let path = out_dir.join(format!("{}.yml", name));
+            fs::write(&path, serde_yaml::to_string(&file)?)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        info!("Generated {} {} job(s) in {}", names.len(), kind.dir(), out_dir.display());
+    }
+    Ok(())
+}
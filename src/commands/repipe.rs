@@ -1,13 +1,42 @@
-use std::{env, path::{Path, PathBuf}, process::Command, fs};
-use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, path::{Path, PathBuf}, process::Command, fs, sync::{Mutex, OnceLock}};
+use serde::Serialize;
 use anyhow::{Result, Context, bail};
-use log::error;
+use log::{debug, info, warn};
 use walkdir::WalkDir;
-use crate::GenesisKitUI;
+use console::style;
+use dialoguer::{Confirm, Input, Select};
+use tabled::{Table, Tabled};
+use crate::{GenesisKitUI, commands::native_jobs, config, flyrc::{FlyRc, Target}, fly::{check_extra_args, visibility_verb, SET_PIPELINE_FLAGS}, git, kits::{self, apply_kit_filter, discover_kits_in, exclude_kits, kit_root, prefixed_pipeline_name, Kit, PipelineMeta, SettingsLookup}, process::{self, RunExt}, say, spruce, ui::{self, diff::render_diff, progress::{self, create_progress_bar, create_spinner}, styles::{dialoguer_theme, heading, palette}}};
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
-#[derive(Debug, Default)]
+/// Tools repipe shells out to besides fly, with where to download them.
+pub(crate) const REQUIRED_TOOLS: &[(&str, Option<&str>)] = &[
+    ("spruce", Some("https://github.com/geofffranks/spruce/releases")),
+    ("jq", None),
+];
+
+const DEFAULT_DEPLOY_FILE: &str = ".deploy.yml";
+
+/// How many lines of fly's stderr a failed step reports.
+const FLY_STDERR_TAIL: usize = 10;
+
+pub(crate) const FLY_DOWNLOAD_URL: Option<&str> = Some("https://concourse-ci.org/fly.html");
+
+/// Locates `cmd` on PATH, saying where to get it when it's missing.
+pub(crate) fn find_tool(cmd: &str, url: Option<&str>) -> Result<PathBuf> {
+    let output = Command::new("which").arg(cmd).run_output();
+    match output {
+        Ok(output) if output.status.success() => {
+            Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+        }
+        _ => bail!("'{}' command not found{}", cmd,
+            url.map(|u| format!("\nDownload from: {}", u))
+                .unwrap_or_else(|| String::from("\nInstall via package manager"))),
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct RepipeOptions {
     pub validate: u8,
     pub dry_run: u8,
@@ -17,91 +46,266 @@ pub struct RepipeOptions {
     pub yes: bool,
     pub fly_path: Option<String>,
     pub debug: bool,
+    /// Keep the deploy file and generated job dirs after the run
+    pub no_cleanup: bool,
+    /// Where to keep a `<pipeline>.yml` copy of each merged config; absolute,
+    /// since repipe changes into the ci directory
+    pub output_dir: Option<PathBuf>,
+    pub target: Option<String>,
+    pub print: bool,
+    pub pipeline: Option<String>,
+    pub validate_only: bool,
+    pub native_jobs: bool,
+    pub skip_scripts: bool,
+    /// Don't run `ci/hooks/pre-repipe` and `ci/hooks/post-repipe` around set-pipeline
+    pub no_hooks: bool,
+    pub parallel_scripts: bool,
+    pub skip_checks: bool,
+    /// Args after `--`, appended to `fly set-pipeline`
+    pub fly_args: Vec<String>,
+    pub deploy_file: Option<String>,
+    pub strict_target: bool,
+    /// Pass `--check-creds` to `fly set-pipeline`
+    pub check_creds: bool,
+    /// Mask secret-looking values in the YAML repipe prints or saves; fly still gets them
+    pub redact: bool,
+    /// Print the resolved meta as JSON once it's extracted
+    pub dump_meta: bool,
+    /// Stop after `--dump-meta`, without checking the target or calling fly
+    pub dump_meta_only: bool,
+    /// `--instance-var NAME=VALUE` pairs making this an instanced pipeline
+    pub instance_vars: Vec<(String, String)>,
+    /// Validate the target against `CONCOURSE_*` even without a complete set, never reading `.flyrc`
+    pub skip_flyrc_check: bool,
+    pub strict: bool,
+    pub env: Option<String>,
+    pub settings: Option<String>,
+    pub since: Option<String>,
+    pub kit_filter: Option<regex::Regex>,
+    pub vault_addr: Option<String>,
+    pub vault_token: Option<String>,
+    /// `--spruce-arg`s, added to the main `spruce merge`
+    pub spruce_args: Vec<String>,
+    /// `--prune`: keys pruned from the merged pipeline instead of the configured ones
+    pub prune: Vec<String>,
+    /// `--exclude-kit`: kits `--all` skips
+    pub exclude_kits: Vec<String>,
+    /// Default for the target prompt, from the last successful repipe.
+    pub last_target: Option<String>,
+}
+
+/// Where a resolved meta field came from, in the order they're tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum MetaSource {
+    /// The kit's `kits:` entry in the gkm config
+    Config,
+    Flag,
+    /// The kit's entry in a `kits.yml` manifest
+    Manifest,
+    Meta,
+    Env,
+    Prompt,
+    Unset,
+}
+
+/// What `--dump-meta` prints: the meta as written, and what repipe resolved from it.
+#[derive(Debug, Serialize)]
+struct MetaDump {
+    meta: PipelineMeta,
+    resolved: BTreeMap<&'static str, ResolvedField>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct PipelineMeta {
-    target: Option<String>,
-    url: Option<String>,
-    team: Option<String>,
-    pipeline: Option<String>,
-    name: Option<String>,
-    exposed: Option<bool>,
+#[derive(Debug, Serialize)]
+struct ResolvedField {
+    value: Option<String>,
+    source: MetaSource,
 }
 
 pub struct RepipeCommand {
     options: RepipeOptions,
     base_dir: PathBuf,
-    settings_file: String,
+    settings_file: PathBuf,
     meta: Option<PipelineMeta>,
     target: String,
     pipeline: String,
+    /// The merged config this run wrote, so cleanup removes only that file.
+    deploy_file: Option<PathBuf>,
 }
 
-impl Drop for RepipeCommand {
-    fn drop(&mut self) {
-        if !self.options.debug {
-            let _ = fs::remove_file(".deploy.yml");
+/// The files a repipe run leaves behind: its deploy file (kept under `--debug`)
+/// and the generated job directories. `--no-cleanup` keeps all of them.
+#[derive(Debug, Clone)]
+struct Cleanup {
+    base_dir: PathBuf,
+    deploy_file: Option<PathBuf>,
+    keep_deploy_file: bool,
+    cleanup: bool,
+}
+
+impl Cleanup {
+    fn run(&self) {
+        if !self.cleanup {
+            debug!("Keeping generated files in {} (--no-cleanup)", self.base_dir.display());
+            return;
+        }
+        if let (false, Some(path)) = (self.keep_deploy_file, &self.deploy_file) {
+            let _ = fs::remove_file(path);
         }
         let _ = fs::remove_dir_all(self.base_dir.join("pipeline").join("upstream"));
         let _ = fs::remove_dir_all(self.base_dir.join("pipeline").join("tests"));
     }
 }
 
+/// Cleanup for the repipe in progress, for when Ctrl-C skips `Drop`.
+static ACTIVE_CLEANUP: Mutex<Option<Cleanup>> = Mutex::new(None);
+
+/// Removes whatever the in-progress repipe has written. Called from the Ctrl-C handler.
+pub fn cleanup_interrupted() {
+    let cleanup = ACTIVE_CLEANUP.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(cleanup) = cleanup {
+        cleanup.run();
+    }
+}
+
+impl Drop for RepipeCommand {
+    fn drop(&mut self) {
+        self.cleanup().run();
+        *ACTIVE_CLEANUP.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
 impl RepipeCommand {
     pub fn new(options: RepipeOptions) -> Result<Self> {
-        let base_dir = Self::find_ci_directory()?;
-        env::set_current_dir(&base_dir)?;
-        Ok(Self { 
+        Self::in_ci_dir(options, Self::find_ci_directory(true)?)
+    }
+
+    fn cleanup(&self) -> Cleanup {
+        Cleanup {
+            base_dir: self.base_dir.clone(),
+            deploy_file: self.deploy_file.clone(),
+            keep_deploy_file: self.options.debug,
+            cleanup: !self.options.no_cleanup,
+        }
+    }
+
+    /// Records what an interrupted run would need to remove.
+    fn register_cleanup(&self) {
+        *ACTIVE_CLEANUP.lock().unwrap_or_else(|e| e.into_inner()) = Some(self.cleanup());
+    }
+
+    /// Works in an already known `ci/` directory rather than searching for one.
+    pub fn in_ci_dir(options: RepipeOptions, base_dir: PathBuf) -> Result<Self> {
+        env::set_current_dir(&base_dir)
+            .with_context(|| format!("Failed to enter {}", base_dir.display()))?;
+        let command = Self { 
             options, 
             base_dir, 
-            settings_file: String::from("settings.yml"), 
+            settings_file: PathBuf::from("settings.yml"),
             meta: None, 
             target: String::new(), 
-            pipeline: String::new() 
-        })
+            pipeline: String::new(),
+            deploy_file: None,
+        };
+        command.register_cleanup();
+        Ok(command)
     }
 
-    fn find_ci_directory() -> Result<PathBuf> {
-        let current_dir = env::current_dir().context("Failed to get current directory")?;
-        error!("Searching for ci directory from: {}", current_dir.display());
-        
-        if current_dir.ends_with("ci") {
-            error!("Current directory ends with 'ci': {}", current_dir.display());
-            Ok(current_dir)
-        } else {
-            let ci_current = current_dir.join("ci");
-            let ci_parent = current_dir.parent().map(|p| p.join("ci"));
-            
-            error!("Checking ci in current dir: {}", ci_current.display());
-            if ci_current.exists() {
-                error!("Found ci directory in current: {}", ci_current.display());
-                return Ok(ci_current);
+    /// The kit root when it is a `ci/` directory, otherwise `./ci` or `../ci`.
+    /// When both exist, `prompt` asks which on a terminal; otherwise it's an error
+    /// naming them both.
+    pub(crate) fn find_ci_directory(prompt: bool) -> Result<PathBuf> {
+        Self::pick_ci_directory(&kit_root()?, prompt)
+    }
+
+    /// The ci directories `base` could mean: itself when it already is one,
+    /// otherwise whichever of `./ci` and `../ci` exist.
+    fn ci_directory_candidates(base: &Path) -> Vec<PathBuf> {
+        if base.ends_with("ci") {
+            debug!("Current directory ends with 'ci': {}", base.display());
+            return vec![base.to_path_buf()];
+        }
+        [Some(base.join("ci")), base.parent().map(|p| p.join("ci"))]
+            .into_iter()
+            .flatten()
+            .filter(|dir| {
+                debug!("Checking for ci directory: {}", dir.display());
+                dir.exists()
+            })
+            .collect()
+    }
+
+    fn pick_ci_directory(current_dir: &Path, prompt: bool) -> Result<PathBuf> {
+        debug!("Searching for ci directory from: {}", current_dir.display());
+        let mut candidates = Self::ci_directory_candidates(current_dir);
+
+        match candidates.len() {
+            0 => {
+                let parent_ci_str = current_dir.parent().map(|p| p.join("ci").display().to_string()).unwrap_or_else(|| "N/A".to_string());
+                let mut message = format!("Could not find ci directory. Checked:\n- Current dir: {}\n- ./ci: {}\n- ../ci: {}",
+                      current_dir.display(),
+                      current_dir.join("ci").display(),
+                      parent_ci_str);
+                message.push_str(&Self::ci_directory_hint(current_dir));
+                bail!(message)
             }
-            
-            let parent_ci_str = ci_parent.as_ref().map(|p| p.display().to_string()).unwrap_or_else(|| "N/A".to_string());
-            if let Some(parent_ci) = ci_parent {
-                error!("Checking ci in parent: {}", parent_ci.display());
-                if parent_ci.exists() {
-                    error!("Found ci directory in parent: {}", parent_ci.display());
-                    return Ok(parent_ci);
-                }
+            1 => {
+                let dir = candidates.remove(0);
+                debug!("Found ci directory: {}", dir.display());
+                Ok(dir)
+            }
+            _ if prompt && ui::is_interactive() => {
+                ui::require_terminal("--kit-root")?;
+                let index = Select::with_theme(&dialoguer_theme())
+                    .with_prompt("Several ci directories found; which one?")
+                    .items(&candidates.iter().map(|dir| dir.display().to_string()).collect::<Vec<_>>())
+                    .default(0)
+                    .interact()
+                    .map_err(|e| ui::prompt_error(e, "--kit-root"))?;
+                Ok(candidates.remove(index))
             }
-            
-            bail!("Could not find ci directory. Checked:\n- Current dir: {}\n- ./ci: {}\n- ../ci: {}", 
-                  current_dir.display(),
-                  ci_current.display(),
-                  parent_ci_str)
+            _ => bail!("Found more than one ci directory:\n{}\nPass --kit-root to pick the kit to use",
+                candidates.iter().map(|dir| format!("- {}", dir.display())).collect::<Vec<_>>().join("\n")),
         }
     }
 
+    /// Suggests where to run from when we're outside a kit repo.
+    fn ci_directory_hint(current_dir: &Path) -> String {
+        let mut hint = String::new();
+
+        match current_dir.ancestors().find(|dir| dir.join(".git").exists()) {
+            Some(repo) => hint.push_str(&format!("\n\nYou are inside the git repository at {}, but it has no ci/ directory here.", repo.display())),
+            None => hint.push_str("\n\nYou don't appear to be inside a git repository; run gk from a kit checkout."),
+        }
+
+        let mut kit_dirs: Vec<PathBuf> = fs::read_dir(current_dir)
+            .map(|entries| entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.join("ci").is_dir())
+                .collect())
+            .unwrap_or_default();
+        kit_dirs.sort();
+
+        if !kit_dirs.is_empty() {
+            hint.push_str("\nThese subdirectories contain a ci/ folder:");
+            for dir in &kit_dirs {
+                let name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                hint.push_str(&format!("\n  cd {}", name));
+            }
+        }
+        hint
+    }
+
+    /// Fails early when spruce, jq or fly are missing, unless `--skip-checks`
+    /// leaves that to the commands themselves.
     fn check_requirements(&self) -> Result<()> {
-        for (cmd, url) in [("spruce", Some("https://github.com/geofffranks/spruce/releases")), 
-                          ("jq", None)] {
-            Command::new("which").arg(cmd).output()
-                .map_err(|_| anyhow::anyhow!("'{}' command not found{}", cmd, 
-                    url.map(|u| format!("\nDownload from: {}", u))
-                        .unwrap_or_else(|| String::from("\nInstall via package manager"))))?;
+        if self.options.skip_checks {
+            debug!("Skipping tool checks (--skip-checks)");
+            return Ok(());
+        }
+        for (cmd, url) in REQUIRED_TOOLS {
+            find_tool(cmd, *url)?;
         }
         
         if let Some(path) = &self.options.fly_path {
@@ -110,25 +314,77 @@ impl RepipeCommand {
                 bail!("Specified fly path '{}' is not executable", path);
             }
         } else { 
-            Command::new("which").arg("fly").output()?; 
+            find_tool("fly", FLY_DOWNLOAD_URL)?;
         }
         Ok(())
     }
 
+    /// Picks the settings file: `--settings`, else the first of
+    /// [`SettingsLookup::candidates`] for `--env` and `--target`/`CONCOURSE_TARGET`.
     fn find_settings_file(&mut self) -> Result<()> {
-        if let Ok(target) = env::var("CONCOURSE_TARGET") {
-            let target_file = format!("settings-{}.yml", target.replace(['/', ' '], "-"));
-            if Path::new(&target_file).exists() {
-                self.settings_file = target_file;
-            }
+        self.settings_file = self.settings_lookup().find(&self.base_dir)?;
+        Ok(())
+    }
+
+    fn settings_lookup(&self) -> SettingsLookup {
+        SettingsLookup {
+            settings: self.options.settings.clone(),
+            env: self.options.env.clone(),
+            target: self.options.target.clone(),
+        }
+    }
+    
+    /// Runs `ci/hooks/<hook>` when the kit has an executable one, with
+    /// `GK_KIT`, `GK_CI_DIR` and, once meta is resolved, `GK_TARGET`/`GK_PIPELINE`
+    /// set. Under `--dry-run` the hook is only printed.
+    fn run_hook(&self, hook: &str) -> Result<()> {
+        let path = self.base_dir.join("hooks").join(hook);
+        if self.options.no_hooks || !path.is_file() {
+            return Ok(());
+        }
+        #[cfg(unix)]
+        if fs::metadata(&path)?.permissions().mode() & 0o111 == 0 {
+            warn!("Skipping {}: it isn't executable", path.display());
+            return Ok(());
+        }
+
+        info!("Running {} hook", hook);
+        let mut command = Command::new(&path);
+        command.env("GK_CI_DIR", &self.base_dir);
+        if let Some(kit) = self.kit_name() {
+            command.env("GK_KIT", kit);
         }
-        if !Path::new(&self.settings_file).exists() {
-            bail!("Missing local settings in ci/settings.yml!");
+        if !self.target.is_empty() {
+            command.env("GK_TARGET", &self.target);
+        }
+        if !self.pipeline.is_empty() {
+            command.env("GK_PIPELINE", &self.pipeline);
+        }
+        if self.options.print {
+            command.stdout(std::process::Stdio::from(std::io::stderr()));
+        }
+        let status = progress::suspend(|| {
+            if process::skip_for_dry_run(&command) {
+                return Ok(None);
+            }
+            command.run_status().map(Some)
+        })?;
+        if let Some(status) = status.filter(|status| !status.success()) {
+            bail!("{} hook failed ({})", hook, status);
         }
         Ok(())
     }
-    
+
     fn execute_build_scripts(&self) -> Result<()> {
+        if self.options.skip_scripts {
+            info!("Skipping build scripts (--skip-scripts); generated jobs may be stale");
+            return Ok(());
+        }
+        if self.options.native_jobs {
+            return native_jobs::generate(&self.base_dir);
+        }
+
+        let mut scripts = Vec::new();
         for script in ["build-test-jobs", "build-upstream-jobs"] {
             let script_path = self.base_dir.join("scripts").join(script);
             if script_path.exists() {
@@ -137,14 +393,85 @@ impl RepipeCommand {
                 #[cfg(windows)]
                 let is_executable = true;
                 if is_executable {
-                    Command::new(&script_path).status()?;
+                    let mut command = Command::new(&script_path);
+                    if self.options.print {
+                        command.stdout(std::process::Stdio::from(std::io::stderr()));
+                    }
+                    scripts.push((script, command));
                 }
             }
         }
+        // The scripts print as they go, so they run with any progress bars cleared
+        progress::suspend(|| self.run_build_scripts(scripts))
+    }
+
+    fn run_build_scripts(&self, scripts: Vec<(&str, Command)>) -> Result<()> {
+        if !self.options.parallel_scripts {
+            for (script, mut command) in scripts {
+                let status = command.run_status().with_context(|| format!("Failed to run {}", script))?;
+                if !status.success() {
+                    bail!("Build script failed: {} ({})", script, status);
+                }
+            }
+            return Ok(());
+        }
+
+        // The scripts write to separate directories, so they can run side by side
+        let mut children = Vec::new();
+        for (script, mut command) in scripts {
+            match command.run_spawn() {
+                Ok(child) => children.push((script, child)),
+                Err(e) => {
+                    // Don't leave the ones already started running behind us
+                    for (_, mut child) in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(e).with_context(|| format!("Failed to start {}", script));
+                }
+            }
+        }
+        let mut failed = Vec::new();
+        for (script, mut child) in children {
+            let status = child.wait().with_context(|| format!("Failed to wait for {}", script))?;
+            if !status.success() {
+                failed.push(format!("{} ({})", script, status));
+            }
+        }
+        if !failed.is_empty() {
+            bail!("Build script failed: {}", failed.join(", "));
+        }
+        Ok(())
+    }
+
+    /// File name the merged config is written to inside the ci directory.
+    fn deploy_file_name(&self) -> &str {
+        self.options.deploy_file.as_deref().unwrap_or(DEFAULT_DEPLOY_FILE)
+    }
+
+    /// Refuses `--deploy-file` names that would land outside the ci directory or
+    /// overwrite one of its inputs: settings files, `pipeline/`, the settings
+    /// file in use, or anything tracked by git.
+    fn check_deploy_file(&self) -> Result<()> {
+        let name = self.deploy_file_name();
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            bail!("--deploy-file must be a plain file name inside the ci directory, not '{}'", name);
+        }
+        if name == "pipeline" || (name.starts_with("settings") && name.ends_with(".yml")) {
+            bail!("--deploy-file '{}' would clobber the kit's own pipeline inputs", name);
+        }
+        let path = self.base_dir.join(name);
+        if path == self.base_dir.join(&self.settings_file) {
+            bail!("--deploy-file '{}' is the settings file being merged", name);
+        }
+        if path.exists() && git::is_tracked(&path)? {
+            bail!("--deploy-file '{}' is tracked by git; pick a name that isn't", name);
+        }
         Ok(())
     }
 
-    fn merge_pipeline_config(&self) -> Result<String> {
+    /// The spruce-merged pipeline, checked but not written anywhere.
+    fn merged_yaml(&self) -> Result<String> {
         let base_yml = self.base_dir.join("pipeline").join("base.yml");
         if !base_yml.exists() { 
             bail!("Missing pipeline/base.yml file"); 
@@ -158,22 +485,36 @@ impl RepipeCommand {
                 .filter_entry(|e| {
                     let path = e.path().to_string_lossy();
                     !path.contains("custom") && !path.contains("optional")
-                }) {
-                if let Ok(entry) = entry {
-                    let path = entry.path().to_path_buf();
-                    if path.extension().map_or(false, |ext| ext == "yml") {
-                        yaml_files.push(path);
-                    }
+                }).flatten() {
+                let path = entry.path().to_path_buf();
+                if path.extension().is_some_and(|ext| ext == "yml") {
+                    yaml_files.push(path);
                 }
             }
         }
 
-        let output = Command::new("spruce")
-            .arg("merge")
-            .arg("--fallback-append")
-            .args(&yaml_files)
-            .arg(&self.settings_file)
-            .output()?;
+        yaml_files.push(self.settings_file.clone());
+        let vault = spruce::Vault {
+            addr: self.options.vault_addr.clone(),
+            token: self.options.vault_token.clone(),
+        };
+        spruce::check_merge_args(&self.options.spruce_args)?;
+        spruce::lint(&yaml_files)?;
+        let operators = spruce::scan_operators(&yaml_files);
+        if !operators.is_empty() {
+            info!("spruce operators in use: {}", operators.iter()
+                .map(|(name, count)| format!("{} ({})", name, count))
+                .collect::<Vec<_>>()
+                .join(", "));
+        }
+        for name in operators.keys().filter(|name| !spruce::is_known_operator(name)) {
+            warn!("'(( {} ))' isn't a spruce operator this tool knows; the merge may fail if your spruce doesn't support it", name);
+        }
+        let uses_vault = operators.contains_key("vault") || operators.contains_key("vault-try");
+        if uses_vault && !vault.is_configured() {
+            warn!("The pipeline uses (( vault )) but no Vault address and token are set; pass --vault-addr/--vault-token or set VAULT_ADDR/VAULT_TOKEN");
+        }
+        let output = spruce::merge(&yaml_files, &vault, &self.options.spruce_args)?;
 
         if !output.status.success() {
             bail!("Failed to merge pipeline configuration: {}", 
@@ -181,149 +522,1166 @@ impl RepipeCommand {
         }
 
         let yaml_output = String::from_utf8(output.stdout)?;
-        serde_yaml::from_str::<serde_yaml::Value>(&yaml_output)?;
+        let documents = spruce::parse_documents(&yaml_output).context("spruce produced invalid YAML")?;
+        let pipeline = documents.first().context("spruce produced an empty pipeline")?;
+        check_pipeline_structure(pipeline, self.options.strict)?;
+        Ok(yaml_output)
+    }
+
+    /// `--prune` keys, else the config's `prune:`, else just `meta`.
+    fn prune_keys(&self) -> Vec<String> {
+        if self.options.prune.is_empty() {
+            config::config().prune_keys()
+        } else {
+            self.options.prune.clone()
+        }
+    }
+
+    /// The merged pipeline as it goes to fly, with the helper keys pruned.
+    fn pruned_yaml(&self, merged: &str) -> Result<String> {
+        spruce::prune(merged, &self.prune_keys())
+    }
+
+    /// Merges and writes the deploy file, returning the full merge (which
+    /// still has `meta`) and the pruned config that is actually set.
+    fn merge_pipeline_config(&mut self) -> Result<(String, String)> {
+        let merged = self.merged_yaml()?;
+        let yaml_output = self.pruned_yaml(&merged)?;
 
         if self.options.debug {
-            fs::write("repipe-debug.yml", &yaml_output)?;
+            fs::write("repipe-debug.yml", self.redacted(&yaml_output)?)?;
             println!("Debug output written to repipe-debug.yml");
             std::process::exit(0);
         }
         
-        fs::write("./.deploy.yml", &yaml_output)?;
-        println!("Pipeline configuration written to .deploy.yml");
-        println!("Current working directory: {:?}", std::env::current_dir()?);
-        Ok(yaml_output)
+        if self.options.print {
+            // stdout is reserved for the YAML itself
+            let cwd = std::env::current_dir()?;
+            progress::suspend(|| {
+                eprintln!("Merged pipeline configuration (not written to {})", self.deploy_file_name());
+                eprintln!("Current working directory: {:?}", cwd);
+            });
+            return Ok((merged, yaml_output));
+        }
+
+        if self.validating() {
+            // Validation works from a temp file so the checkout stays clean
+            return Ok((merged, yaml_output));
+        }
+
+        let deploy_file = self.base_dir.join(self.deploy_file_name());
+        fs::write(&deploy_file, &yaml_output)
+            .with_context(|| format!("Failed to write {}", deploy_file.display()))?;
+        say!("Pipeline configuration written to {}", self.deploy_file_name());
+        self.deploy_file = Some(deploy_file);
+        self.register_cleanup();
+        say!("Current working directory: {:?}", std::env::current_dir()?);
+        Ok((merged, yaml_output))
     }
 
     fn extract_meta(&mut self, config: &str) -> Result<()> {
-        let mut child = Command::new("spruce")
-            .args(&["merge", "--skip-eval", "--cherry-pick", "meta"])
-            .arg("-")
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .spawn()?;
-
-        if let Some(mut stdin) = child.stdin.take() {
-            use std::io::Write;
-            stdin.write_all(config.as_bytes())?;
+        let dump = self.resolve_meta(config)?;
+        if self.options.dump_meta {
+            let json = serde_json::to_string_pretty(&dump)?;
+            progress::suspend(|| println!("{}", json));
         }
+        Ok(())
+    }
 
-        let output = child.wait_with_output()?;
-        #[derive(Deserialize)]
-        struct MetaWrapper { meta: PipelineMeta }
-        let wrapper: MetaWrapper = serde_yaml::from_str(&String::from_utf8(output.stdout)?)?;
-        self.meta = Some(wrapper.meta);
+    /// Resolves target, url, team and pipeline from `config`'s meta and their
+    /// fallbacks, returning where each came from for `--dump-meta`.
+    fn resolve_meta(&mut self, config: &str) -> Result<MetaDump> {
+        self.resolve_meta_with(config, config::config())
+    }
 
-        if let Some(meta) = &self.meta {
-            self.target = meta.target.clone()
-                .or_else(|| env::var("CONCOURSE_TARGET").ok())
-                .context("Missing target")?;
-            self.pipeline = meta.pipeline.clone()
+    /// [`Self::resolve_meta`] with the `kits:` overrides taken from `settings`.
+    fn resolve_meta_with(&mut self, config: &str, settings: &config::Config) -> Result<MetaDump> {
+        let mut meta = PipelineMeta::from_merged(config)?;
+
+        // Like target, url and team fall back to the environment when meta is minimal
+        let url = pick([(MetaSource::Meta, meta.url.clone()), (MetaSource::Env, env::var("CONCOURSE_URL").ok())]);
+        // The kit's `kits:` entry in the gkm config overrides flags and meta alike
+        let configured = self.kit_name().and_then(|kit| settings.kit(&kit).cloned()).unwrap_or_default();
+        let manifest = kits::manifest_kit(&self.base_dir);
+        let team = pick([
+            (MetaSource::Config, configured.team),
+            (MetaSource::Manifest, manifest.as_ref().and_then(|m| m.team.clone())),
+            (MetaSource::Meta, meta.team.clone()),
+            (MetaSource::Env, env::var("CONCOURSE_TEAM").ok()),
+        ]);
+        let written = meta.clone();
+
+        let target = match pick([
+            (MetaSource::Config, configured.target),
+            (MetaSource::Flag, self.options.target.clone()),
+            (MetaSource::Manifest, manifest.as_ref().and_then(|m| m.target.clone())),
+            (MetaSource::Meta, meta.target.clone()),
+            (MetaSource::Env, env::var("CONCOURSE_TARGET").ok()),
+        ]) {
+            (source, Some(target)) => (source, target),
+            _ if ui::is_interactive() => (MetaSource::Prompt, self.prompt_target()?),
+            _ => bail!("Pipeline has no `meta.target`; pass --target or set CONCOURSE_TARGET"),
+        };
+        self.target = target.1;
+        // An explicit --pipeline renames the pipeline exactly, without --pipeline-prefix;
+        // target and team still come from meta
+        let pipeline = match (&self.options.pipeline, manifest.as_ref().and_then(|m| m.pipeline.as_deref())) {
+            (Some(pipeline), _) => (MetaSource::Flag, pipeline.clone()),
+            (None, Some(pipeline)) => (MetaSource::Manifest, prefixed_pipeline_name(pipeline)),
+            (None, None) => (MetaSource::Meta, prefixed_pipeline_name(&meta.pipeline.clone()
                 .or_else(|| meta.name.clone())
-                .context("Missing pipeline name")?;
+                .context("Pipeline has no `meta.pipeline` or `meta.name`; pass --pipeline")?)),
+        };
+        self.pipeline = pipeline.1;
+        let dump = MetaDump {
+            meta: written,
+            resolved: BTreeMap::from([
+                ("target", ResolvedField { value: Some(self.target.clone()), source: target.0 }),
+                ("url", ResolvedField { value: url.1.clone(), source: url.0 }),
+                ("team", ResolvedField { value: team.1.clone(), source: team.0 }),
+                ("pipeline", ResolvedField { value: Some(self.pipeline.clone()), source: pipeline.0 }),
+            ]),
+        };
+        meta.url = url.1;
+        meta.team = team.1;
+        self.meta = Some(meta);
+        Ok(dump)
+    }
+
+    /// The kit this ci directory belongs to: the name of its `kits.yml` entry,
+    /// else of its parent directory.
+    fn kit_name(&self) -> Option<String> {
+        kits::manifest_kit(&self.base_dir).map(|kit| kit.name)
+            .or_else(|| self.base_dir.parent()?.file_name().map(|n| n.to_string_lossy().into_owned()))
+    }
+
+    fn prompt_target(&self) -> Result<String> {
+        let theme = dialoguer_theme();
+        let mut input = Input::<String>::with_theme(&theme)
+            .with_prompt("Pipeline has no `meta.target`; Concourse target");
+        if let Some(last) = &self.options.last_target {
+            input = input.default(last.clone());
         }
-        Ok(())
+        Ok(progress::suspend(|| input.interact_text())?)
     }
 
+    /// Checks the target exists and agrees with meta. A complete `CONCOURSE_*`
+    /// environment (URL, team and token) takes precedence over `.flyrc`;
+    /// `--skip-flyrc-check` never reads `.flyrc` and checks against whatever
+    /// of the environment is set.
     fn validate_target(&self) -> Result<()> {
-        let flyrc_path = [
-            dirs::home_dir().map(|p| p.join(".flyrc")),
-            env::var("FLYRC").ok().map(PathBuf::from),
-            Some(PathBuf::from(".flyrc")),
-        ].into_iter().flatten().find(|p| p.exists())
-            .context("Could not find .flyrc file")?;
+        validate_target_name(&self.target)?;
 
-        let flyrc: serde_yaml::Value = serde_yaml::from_str(&fs::read_to_string(flyrc_path)?)?;
-        let targets = flyrc.get("targets").context("No targets in .flyrc")?;
-
-        if !targets.get(&self.target).is_some() {
-            bail!("Target '{}' not found", self.target);
-        }
+        let from_env = self.options.skip_flyrc_check || Target::env_is_complete();
+        let (target, source) = if from_env {
+            debug!("Validating target '{}' against the CONCOURSE_* environment", self.target);
+            (Target::from_env(), String::from("the environment"))
+        } else {
+            let flyrc = FlyRc::shared()?;
+            let Some(target) = flyrc.target(&self.target) else {
+                bail!("Target '{}' not found in {} (set CONCOURSE_URL, CONCOURSE_TEAM and CONCOURSE_TOKEN, or pass --skip-flyrc-check, to go without .flyrc)",
+                    self.target, flyrc.path.display());
+            };
+            (target.clone(), flyrc.path.display().to_string())
+        };
 
         if let Some(meta) = &self.meta {
-            if let Some(url) = &meta.url {
-                if url != targets[&self.target]["api"].as_str().unwrap_or_default() {
-                    bail!("Target URL mismatch");
+            for (field, wanted, configured) in [("url", &meta.url, &target.api), ("team", &meta.team, &target.team)] {
+                let Some(wanted) = wanted else { continue };
+                if from_env && configured.is_none() {
+                    continue;
                 }
-            }
-            if let Some(team) = &meta.team {
-                if team != targets[&self.target]["team"].as_str().unwrap_or_default() {
-                    bail!("Target team mismatch");
+                let configured = configured.as_deref().unwrap_or_default();
+                if wanted == configured {
+                    continue;
+                }
+                let message = format!("Target {} mismatch for '{}': meta.{} is '{}' but {} has '{}'",
+                    field, self.target, field, wanted, source, configured);
+                if self.options.strict_target {
+                    bail!(message);
                 }
+                warn!("{} (pass --strict-target to treat this as an error)", message);
             }
         }
         Ok(())
     }
 
-    pub fn execute(&mut self) -> Result<()> {
+    /// Decides pipeline visibility: an explicit `--expose`/`--no-expose` wins,
+    /// then `meta.exposed`, and otherwise the pipeline stays hidden.
+    fn resolve_expose(&self) -> bool {
+        let meta_exposed = self.meta.as_ref().and_then(|m| m.exposed);
+        let (expose, source) = match (self.options.expose, meta_exposed) {
+            (Some(flag), _) => (flag, "command-line flag"),
+            (None, Some(exposed)) => (exposed, "meta.exposed"),
+            (None, None) => (false, "default"),
+        };
+        info!("Pipeline will be {} (decided by {})", if expose { "exposed" } else { "hidden" }, source);
+        expose
+    }
+
+    /// How fly names the pipeline outside `set-pipeline`: `name/key:value,...`
+    /// once `--instance-var`s make it an instanced pipeline.
+    fn pipeline_ref(&self) -> String {
+        if self.options.instance_vars.is_empty() {
+            return self.pipeline.clone();
+        }
+        let vars: Vec<String> = self.options.instance_vars.iter()
+            .map(|(name, value)| format!("{}:{}", name, value))
+            .collect();
+        format!("{}/{}", self.pipeline, vars.join(","))
+    }
+
+    fn pipeline_url(&self) -> Option<String> {
+        let mut url = self.meta.as_ref().map(|meta| format!("{}/teams/{}/pipelines/{}",
+            meta.url.as_deref().unwrap_or_default(),
+            meta.team.as_deref().unwrap_or_default(),
+            self.pipeline
+        ))?;
+        // The web UI picks an instance by its vars, each as a JSON value
+        let vars: Vec<String> = self.options.instance_vars.iter()
+            .map(|(name, value)| format!("vars.{}={}", name, percent_encode(&serde_json::Value::from(value.as_str()).to_string())))
+            .collect();
+        if !vars.is_empty() {
+            url = format!("{}?{}", url, vars.join("&"));
+        }
+        Some(url)
+    }
+
+    fn result(&self, action: RepipeAction, exposed: Option<bool>) -> RepipeResult {
+        let set = action == RepipeAction::Set;
+        RepipeResult {
+            action,
+            target: self.target.clone(),
+            team: self.meta.as_ref().and_then(|m| m.team.clone()),
+            pipeline: self.pipeline_ref(),
+            exposed,
+            paused: set.then_some(self.options.pause),
+            url: self.pipeline_url(),
+        }
+    }
+
+    /// `fly set-pipeline` for the deploy file under the resolved target and pipeline name.
+    fn set_pipeline_command(&self, fly: &str, non_interactive: bool) -> Command {
+        let mut set = Command::new(fly);
+        set.args(["--target", &self.target, "set-pipeline", "--pipeline", &self.pipeline])
+            .arg("--config").arg(self.base_dir.join(self.deploy_file_name()));
+        if non_interactive {
+            set.arg("--non-interactive");
+        }
+        for (name, value) in &self.options.instance_vars {
+            set.arg("--instance-var").arg(format!("{}={}", name, value));
+        }
+        if self.options.check_creds {
+            set.arg("--check-creds");
+        }
+        set.args(&self.options.fly_args);
+        set
+    }
+
+    /// `fly pause-pipeline`, or `unpause-pipeline` without `--pause`.
+    fn pause_command(&self, fly: &str) -> Command {
+        let verb = if self.options.pause { "pause-pipeline" } else { "unpause-pipeline" };
+        let mut pause = Command::new(fly);
+        pause.args(["--target", &self.target, verb, "--pipeline", &self.pipeline_ref()]);
+        pause
+    }
+
+    fn visibility_command(&self, fly: &str, exposed: bool) -> Command {
+        let mut visibility = Command::new(fly);
+        visibility.args(["--target", &self.target, visibility_verb(exposed), "--pipeline", &self.pipeline_ref()]);
+        visibility
+    }
+
+    fn set_pipeline_visibility(&self, fly: &str, exposed: bool) -> Result<()> {
+        run_fly_step(visibility_verb(exposed), &mut self.visibility_command(fly, exposed))
+    }
+
+    /// Diffs the live pipeline against the merged config and asks before
+    /// setting it. A pipeline that doesn't exist yet diffs against nothing.
+    fn confirm_changes(&self, fly: &str, config: &str) -> Result<bool> {
+        let live = Command::new(fly)
+            .args(["--target", &self.target, "get-pipeline", "--pipeline", &self.pipeline_ref()])
+            .run_output()
+            .context("Failed to run fly get-pipeline")?;
+        let current = if live.status.success() {
+            String::from_utf8_lossy(&live.stdout).into_owned()
+        } else {
+            String::new()
+        };
+
+        let Some(diff) = render_diff(&self.redacted(&current)?, &self.redacted(config)?, "live", self.deploy_file_name()) else {
+            progress::suspend(|| println!("{}", style("No pipeline changes").dim()));
+            return Ok(true);
+        };
+        Ok(progress::suspend(|| {
+            say!("\n{}", heading("📝 PIPELINE CHANGES"));
+            println!("{}", diff);
+            Confirm::with_theme(&dialoguer_theme())
+                .with_prompt(format!("Set pipeline {} on {}?", self.pipeline_ref(), self.target))
+                .default(false)
+                .interact()
+        })?)
+    }
+
+    /// `yaml` as shown to people: under `--redact`, with secrets masked by [`redact`].
+    fn redacted(&self, yaml: &str) -> Result<String> {
+        if !self.options.redact || yaml.trim().is_empty() {
+            return Ok(yaml.to_string());
+        }
+        let documents = spruce::parse_documents(yaml)?;
+        let mut out = Vec::with_capacity(documents.len());
+        for mut document in documents {
+            redact(&mut document, false);
+            out.push(serde_yaml::to_string(&document)?);
+        }
+        Ok(out.join("---\n"))
+    }
+
+    fn validating(&self) -> bool {
+        self.options.validate_only || (self.options.validate > 0 && self.options.dry_run == 0)
+    }
+
+    /// Runs `fly validate-pipeline` against a temp copy of the merged config.
+    fn validate_pipeline(&self, fly: &str, config: &str) -> Result<()> {
+        let mut file = tempfile::Builder::new()
+            .prefix("gk-validate-")
+            .suffix(".yml")
+            .tempfile()
+            .context("Failed to create temporary pipeline file")?;
+        {
+            use std::io::Write;
+            file.write_all(config.as_bytes())
+                .context("Failed to write temporary pipeline file")?;
+        }
+
+        let mut cmd = Command::new(fly);
+        if !self.target.is_empty() {
+            cmd.args(["--target", &self.target]);
+        }
+        cmd.arg("validate-pipeline");
+        if self.options.validate >= 2 {
+            cmd.arg("--strict");
+        }
+        let status = progress::suspend(|| cmd.arg("--config").arg(file.path()).run_status())?;
+        if !status.success() {
+            bail!("Pipeline validation failed");
+        }
+        Ok(())
+    }
+
+    /// `--output-dir`: keeps a copy of the merged config as `<dir>/<pipeline>.yml`,
+    /// named after the full pipeline ref with `/`, `:` and `\` turned into `_`.
+    /// It's only an artifact; fly is always handed the deploy file.
+    fn write_output_file(&self, config: &str) -> Result<()> {
+        let Some(dir) = &self.options.output_dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let name = self.pipeline_ref().replace(['/', ':', '\\'], "_");
+        let path = dir.join(format!("{}.yml", name));
+        fs::write(&path, self.redacted(config)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        say!("Merged pipeline saved to {}", path.display());
+        Ok(())
+    }
+
+    /// Merges as `execute` would and returns the result, without writing the
+    /// deploy file, running hooks or touching fly. Build scripts only run with
+    /// `run_scripts`; otherwise the job files they last generated are merged.
+    pub fn merged_config(&mut self, run_scripts: bool) -> Result<String> {
         self.check_requirements()?;
         self.find_settings_file()?;
+        if run_scripts {
+            self.execute_build_scripts()?;
+        }
+        let merged = self.merged_yaml()?;
+        self.pruned_yaml(&merged)
+    }
+
+    pub fn execute(&mut self) -> Result<RepipeResult> {
+        check_extra_args(&self.options.fly_args, SET_PIPELINE_FLAGS)?;
+        self.check_requirements()?;
+        self.find_settings_file()?;
+        self.check_deploy_file()?;
         self.execute_build_scripts()?;
-        
-        let config = self.merge_pipeline_config()?;
+
+        let (merged, config) = self.merge_pipeline_config()?;
         // If debug flag is set, merge_pipeline_config will exit early
+
+        if self.options.print {
+            print!("{}", self.redacted(&config)?);
+            return Ok(self.result(RepipeAction::Printed, None));
+        }
         
-        self.extract_meta(&config)?;
+        let fly = self.options.fly_path.clone().unwrap_or_else(|| String::from("fly"));
+        if self.options.validate_only {
+            self.validate_pipeline(&fly, &config)?;
+            return Ok(self.result(RepipeAction::Validated, None));
+        }
+
+        self.extract_meta(&merged)?;
+        // Kept even when the target turns out to be unusable, to see what would have been set
+        self.write_output_file(&config)?;
+        if self.options.dump_meta_only {
+            return Ok(self.result(RepipeAction::MetaDumped, None));
+        }
         self.validate_target()?;
 
-        let fly = self.options.fly_path.clone().unwrap_or_else(|| String::from("fly"));
-        match (self.options.validate, self.options.dry_run) {
+        let result = match (self.options.validate, self.options.dry_run) {
             (v, 0) if v > 0 => {
-                Command::new(&fly)
-                    .args(&["--target", &self.target, "validate-pipeline"])
-                    .arg(if v >= 2 { "--strict" } else { "" })
-                    .arg("--config").arg(".deploy.yml")
-                    .status()?;
-            },
-            (0, d) if d > 0 => println!("{}", fs::read_to_string(".deploy.yml")?),
+                self.validate_pipeline(&fly, &config)?;
+                self.result(RepipeAction::Validated, None)
+            }
             _ => {
-                Command::new(&fly)
-                    .args(&["--target", &self.target, "set-pipeline", "--pipeline", &self.pipeline])
-                    .args(&["--config", ".deploy.yml"])
-                    .arg(if self.options.yes { "--non-interactive" } else { "" })
-                    .status()?;
-
-                Command::new(&fly)
-                    .args(&["--target", &self.target, 
-                           &format!("{}-pipeline", if self.options.pause { "pause" } else { "unpause" })])
-                    .args(&["--pipeline", &self.pipeline])
-                    .status()?;
-
-                let expose = self.options.expose
-                    .unwrap_or_else(|| self.meta.as_ref().and_then(|m| m.exposed).unwrap_or(false));
-                Command::new(&fly)
-                    .args(&["--target", &self.target])
-                    .args(&[if expose { "expose-pipeline" } else { "hide-pipeline" }])
-                    .args(&["--pipeline", &self.pipeline])
-                    .status()?;
+                // We show our own diff and prompt, so fly doesn't need to ask
+                // again; a dry run only prints the fly commands, so there's nothing to confirm
+                let dry_run = self.options.dry_run > 0;
+                let confirm = !self.options.yes && !dry_run && ui::is_interactive();
+                if confirm && !self.confirm_changes(&fly, &config)? {
+                    progress::suspend(|| println!("{}", style("Aborted: pipeline not updated").fg(palette().warning)));
+                    return Ok(self.result(RepipeAction::Aborted, None));
+                }
+
+                // Only runs that go on to set the pipeline get here, with meta resolved
+                self.run_hook("pre-repipe")?;
+                let set_step = run_fly_step("set-pipeline", &mut self.set_pipeline_command(&fly, self.options.yes || confirm));
+                if self.options.check_creds {
+                    set_step.context("Pipeline not set: fly's credential check failed (the missing vars are listed below)")?;
+                } else {
+                    set_step?;
+                }
+
+                let verb = if self.options.pause { "pause-pipeline" } else { "unpause-pipeline" };
+                run_fly_step(verb, &mut self.pause_command(&fly))?;
+
+                let expose = self.resolve_expose();
+                self.set_pipeline_visibility(&fly, expose)?;
+                if dry_run {
+                    self.result(RepipeAction::DryRun, None)
+                } else {
+                    // The pipeline is already set, so a failing hook shouldn't fail the run
+                    if let Err(e) = self.run_hook("post-repipe") {
+                        warn!("{:#}", e);
+                    }
+                    self.result(RepipeAction::Set, Some(expose))
+                }
             }
-        }
+        };
 
         if self.options.open_browser > 0 {
-            if let Some(meta) = &self.meta {
-                let url = format!("{}/teams/{}/pipelines/{}", 
-                    meta.url.as_ref().unwrap_or(&String::new()),
-                    meta.team.as_ref().unwrap_or(&String::new()),
-                    self.pipeline
-                );
-                if let Err(e) = open::that(&url) {
-                    println!("Could not open browser automatically: {}\nURL: {}", e, url);
+            if let Some(url) = &result.url {
+                if let Err(e) = open::that(url) {
+                    progress::suspend(|| println!("Could not open browser automatically: {}\nURL: {}", e, url));
                 }
             }
         }
-        Ok(())
+        Ok(result)
     }
 }
 
+/// What a repipe run ended up doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepipeAction {
+    /// `--print`: the merged YAML went to stdout
+    Printed,
+    /// `--validate`/`--validate-only`: fly checked the config
+    Validated,
+    /// `--dry-run`: the fly commands were printed instead of run
+    DryRun,
+    /// `--dump-meta-only`: the resolved meta was printed
+    MetaDumped,
+    /// The diff was declined at the prompt
+    Aborted,
+    /// The pipeline was uploaded, paused or unpaused, and exposed or hidden
+    Set,
+}
+
+impl RepipeAction {
+    fn label(self) -> &'static str {
+        match self {
+            RepipeAction::Printed => "printed",
+            RepipeAction::Validated => "validated",
+            RepipeAction::DryRun => "dry run",
+            RepipeAction::MetaDumped => "meta dumped",
+            RepipeAction::Aborted => "aborted",
+            RepipeAction::Set => "repiped",
+        }
+    }
+}
+
+/// The outcome of [`RepipeCommand::execute`]. Target, team, pipeline and URL
+/// are only known once meta has been read, so `--print` and
+/// `--validate-only` leave them empty.
+#[derive(Debug, Clone)]
+pub struct RepipeResult {
+    pub action: RepipeAction,
+    pub target: String,
+    pub team: Option<String>,
+    pub pipeline: String,
+    /// Whether the pipeline is now public; `None` unless it was set
+    pub exposed: Option<bool>,
+    /// Whether the pipeline is now paused; `None` unless it was set
+    pub paused: Option<bool>,
+    pub url: Option<String>,
+}
+
+impl RepipeResult {
+    /// The summary printed after a pipeline is set; other actions print nothing.
+    pub fn print_summary(&self) {
+        let (Some(paused), Some(exposed)) = (self.paused, self.exposed) else {
+            return;
+        };
+        say!("\n{}", heading("📊 REPIPE SUMMARY"));
+        println!("Target:     {}", style(&self.target).fg(palette().success));
+        println!("Team:       {}", style(self.team.as_deref().unwrap_or("(target default)")).fg(palette().success));
+        println!("Pipeline:   {}", style(&self.pipeline).fg(palette().success));
+        println!("State:      {}", if paused { style("paused").fg(palette().warning) } else { style("unpaused").fg(palette().success) });
+        println!("Visibility: {}", if exposed { style("exposed").fg(palette().warning) } else { style("hidden").fg(palette().success) });
+        if let Some(url) = &self.url {
+            println!("URL:        {}", style(url).fg(palette().info));
+        }
+    }
+}
+
+/// Catches merges that no longer look like a Concourse pipeline (no `jobs:`,
+/// no resources) before fly reports them less clearly. Warnings unless `strict`.
+fn check_pipeline_structure(pipeline: &serde_yaml::Value, strict: bool) -> Result<()> {
+    let mut problems = Vec::new();
+    match pipeline.get("jobs") {
+        None => problems.push("Merged pipeline has no `jobs:`; did a settings file overwrite the pipeline?"),
+        Some(jobs) if jobs.as_sequence().is_none_or(|jobs| jobs.is_empty()) => {
+            problems.push("Merged pipeline's `jobs:` is empty")
+        }
+        Some(_) => {}
+    }
+    if pipeline.get("resources").and_then(|r| r.as_sequence()).is_none_or(|r| r.is_empty()) {
+        problems.push("Merged pipeline has no `resources:`");
+    }
+
+    if strict && !problems.is_empty() {
+        bail!("{}", problems.join("\n"));
+    }
+    for problem in problems {
+        warn!("{}", problem);
+    }
+    Ok(())
+}
+
+/// Runs one fly step with the terminal attached but stderr captured, so a
+/// failure can name the step and show what fly said.
+fn run_fly_step(step: &str, cmd: &mut Command) -> Result<()> {
+    let output = progress::suspend(|| cmd
+        .stdin(std::process::Stdio::inherit())
+        .stdout(std::process::Stdio::inherit())
+        .run_change())
+        .with_context(|| format!("Failed to run fly {}", step))?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        let lines: Vec<&str> = stderr.lines().collect();
+        let tail = lines[lines.len().saturating_sub(FLY_STDERR_TAIL)..].join("\n");
+        bail!("fly {} failed ({}):\n{}", step, output.status, tail);
+    }
+    progress::suspend(|| eprint!("{}", stderr));
+    Ok(())
+}
+
+/// Replaces every value under a key with a `password`, `token`, `secret` or
+/// `key` segment (`api_key`, `private-key`, but not `monkey` or `keys`) with
+/// `***`; `masked` is set once such a key encloses `value`. Concourse
+/// `((var))` references hold no secret and are left alone.
+fn redact(value: &mut serde_yaml::Value, masked: bool) {
+    static SECRET_KEY: OnceLock<regex::Regex> = OnceLock::new();
+    let secret_key = SECRET_KEY.get_or_init(|| {
+        regex::Regex::new(r"(?i)(^|[_-])(password|token|secret|key)($|[_-])").unwrap()
+    });
+
+    match value {
+        serde_yaml::Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                redact(value, masked || key.as_str().is_some_and(|key| secret_key.is_match(key)));
+            }
+        }
+        serde_yaml::Value::Sequence(items) => items.iter_mut().for_each(|item| redact(item, masked)),
+        serde_yaml::Value::Tagged(tagged) => redact(&mut tagged.value, masked),
+        serde_yaml::Value::Null => {}
+        serde_yaml::Value::String(s) if s.trim_start().starts_with("((") => {}
+        leaf if masked => *leaf = serde_yaml::Value::from("***"),
+        _ => {}
+    }
+}
+
+/// Names of the kits whose checkout (the parent of their `ci/`) holds one of
+/// `paths`, which are canonical as [`git::changed_since`] returns them.
+fn changed_kits(kits: &[Kit], paths: &[PathBuf]) -> Vec<String> {
+    kits.iter()
+        .filter(|kit| {
+            let Some(dir) = kit.path.parent().and_then(|dir| dir.canonicalize().ok()) else {
+                return false;
+            };
+            paths.iter().any(|path| path.starts_with(&dir))
+        })
+        .map(|kit| kit.name.clone())
+        .collect()
+}
+
+/// The first candidate with a value, with its source; `Unset` when none has one.
+fn pick<const N: usize>(candidates: [(MetaSource, Option<String>); N]) -> (MetaSource, Option<String>) {
+    candidates.into_iter()
+        .find(|(_, value)| value.is_some())
+        .unwrap_or((MetaSource::Unset, None))
+}
+
+/// Percent-encodes everything but RFC 3986's unreserved characters.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Checks a target name against the characters fly accepts, so a malformed
+/// name isn't reported as merely missing from `.flyrc`.
+fn validate_target_name(target: &str) -> Result<()> {
+    if target.is_empty() {
+        bail!("Invalid target name format: target name is empty");
+    }
+    if let Some(bad) = target.chars().find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))) {
+        bail!("Invalid target name format '{}': found {:?}; fly target names may only contain letters, digits, '-', '_' and '.'",
+              target, bad);
+    }
+    Ok(())
+}
+
+/// One kit's row in the `--all` summary table.
+#[derive(Tabled)]
+struct KitOutcome {
+    #[tabled(rename = "Kit")]
+    kit: String,
+    #[tabled(rename = "Result")]
+    result: String,
+    #[tabled(rename = "Details")]
+    details: String,
+    #[tabled(skip)]
+    failed: bool,
+}
+
 impl GenesisKitUI {
-    pub fn repipe_interactive(&self) {
-        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-            .format_timestamp(Some(env_logger::TimestampPrecision::Seconds))
-            .format_module_path(true)
-            .init();
+    /// Repipes every kit under the kit root in turn, with a line per kit while
+    /// it runs and a results table at the end.
+    pub fn repipe_all(&self, options: RepipeOptions) -> Result<()> {
+        let root = kit_root()?;
+        let kits: Vec<Kit> = discover_kits_in(&root)?
+            .into_iter()
+            .filter(|kit| kit.manifest.is_some() || kit.path == root.join(&kit.name).join("ci"))
+            .collect();
+        if kits.is_empty() {
+            bail!("No kits with a ci/ directory found under {}", root.display());
+        }
+        let kits = exclude_kits(apply_kit_filter(kits, options.kit_filter.as_ref())?, &options.exclude_kits)?;
+
+        // Kits whose directory holds a file changed since the ref; `None` means all of them
+        let changed: Option<Vec<String>> = match &options.since {
+            Some(since) => Some(changed_kits(&kits, &git::changed_since(&root, since)?)),
+            None => None,
+        };
+
+        say!("\n{}\n", heading("🔄 REPIPING ALL KITS"));
+        let overall = create_progress_bar(&self.multi_progress, kits.len() as u64, "Repiping kits...");
+        let lines: Vec<_> = kits.iter()
+            .map(|kit| {
+                let line = create_spinner(&self.multi_progress, &kit.name, "pending");
+                line.disable_steady_tick();
+                line
+            })
+            .collect();
+
+        progress::set_active(Some(self.multi_progress.clone()));
+        let mut outcomes = Vec::with_capacity(kits.len());
+        for (kit, line) in kits.iter().zip(&lines) {
+            if changed.as_ref().is_some_and(|changed| !changed.contains(&kit.name)) {
+                line.finish_with_message(style("– skipped").dim().to_string());
+                let since = options.since.as_deref().unwrap_or_default();
+                outcomes.push(KitOutcome {
+                    kit: kit.name.clone(),
+                    result: String::from("– skipped"),
+                    details: format!("no changes since {}", since),
+                    failed: false,
+                });
+                overall.inc(1);
+                continue;
+            }
+
+            line.enable_steady_tick(std::time::Duration::from_millis(100));
+            line.set_message("running");
+
+            // The kit clears the bars itself around its own output and prompts
+            let result = RepipeCommand::in_ci_dir(options.clone(), kit.path.clone())
+                .and_then(|mut cmd| cmd.execute());
+            if let Ok(result) = &result {
+                progress::suspend(|| result.print_summary());
+            }
+
+            let (result, details, failed) = match result {
+                Ok(result) => {
+                    line.finish_with_message(style("✓ done").fg(palette().success).to_string());
+                    let details = if result.target.is_empty() {
+                        String::new()
+                    } else {
+                        format!("{} on {}", result.pipeline, result.target)
+                    };
+                    (format!("✓ {}", result.action.label()), details, false)
+                }
+                Err(e) => {
+                    line.finish_with_message(style("✗ failed").fg(palette().error).to_string());
+                    let summary = format!("{:#}", e);
+                    (String::from("✗ failed"), summary.lines().next().unwrap_or_default().to_string(), true)
+                }
+            };
+            outcomes.push(KitOutcome { kit: kit.name.clone(), result, details, failed });
+            overall.inc(1);
+        }
+        overall.finish_with_message("Done");
+        progress::set_active(None);
+        env::set_current_dir(&root)?;
+
+        say!("\n{}\n", heading("📊 REPIPE RESULTS"));
+        println!("{}", Table::new(&outcomes));
+
+        let failed = outcomes.iter().filter(|o| o.failed).count();
+        if failed > 0 {
+            bail!("{} of {} kits failed to repipe", failed, outcomes.len());
+        }
+        Ok(())
+    }
+
+    pub fn repipe_interactive(&self, mut options: RepipeOptions) -> Result<()> {
+        options.last_target = self.last_target();
+        let result = RepipeCommand::new(options)
+            .and_then(|mut cmd| cmd.execute())
+            .context("Repipe failed")?;
+        result.print_summary();
+        if !result.target.is_empty() {
+            self.remember_target(&result.target);
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(text: &str) -> serde_yaml::Value {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    /// A command for a `bosh` kit in a scratch directory, without entering it.
+    fn command(options: RepipeOptions) -> (tempfile::TempDir, RepipeCommand) {
+        let root = tempfile::tempdir().unwrap();
+        let base_dir = root.path().join("bosh").join("ci");
+        fs::create_dir_all(&base_dir).unwrap();
+        (root, command_in(base_dir, options))
+    }
+
+    fn command_in(base_dir: PathBuf, options: RepipeOptions) -> RepipeCommand {
+        RepipeCommand {
+            options,
+            base_dir,
+            settings_file: PathBuf::from("settings.yml"),
+            meta: None,
+            target: String::new(),
+            pipeline: String::new(),
+            deploy_file: None,
+        }
+    }
+
+    /// Holds the env lock with the `CONCOURSE_*` variables cleared, then set to `vars`.
+    fn concourse_env(vars: &[(&str, &str)]) -> std::sync::MutexGuard<'static, ()> {
+        let guard = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        for name in ["CONCOURSE_TARGET", "CONCOURSE_URL", "CONCOURSE_TEAM"] {
+            env::remove_var(name);
+        }
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        ui::set_interactive(Some(false));
+        guard
+    }
+
+    #[test]
+    fn empty_or_null_meta_leans_on_flags() {
+        let _env = concourse_env(&[]);
+        for pipeline in ["meta:\njobs: []\n", "meta: ~\njobs: []\n", "jobs: []\n"] {
+            let options = RepipeOptions { target: Some(String::from("ci")), pipeline: Some(String::from("bosh-dev")), ..Default::default() };
+            let (_root, mut cmd) = command(options);
+            cmd.extract_meta(pipeline).unwrap();
+            assert_eq!((cmd.target.as_str(), cmd.pipeline.as_str()), ("ci", "bosh-dev"), "{:?}", pipeline);
+            assert_eq!(cmd.meta, Some(PipelineMeta::default()));
+        }
+    }
+
+    #[test]
+    fn null_meta_without_a_pipeline_name_asks_for_one() {
+        let _env = concourse_env(&[]);
+        let (_root, mut cmd) = command(RepipeOptions { target: Some(String::from("ci")), ..Default::default() });
+        let err = cmd.extract_meta("meta: ~\n").unwrap_err();
+        assert!(err.to_string().contains("pass --pipeline"), "{}", err);
+    }
+
+    #[test]
+    fn missing_target_falls_back_to_the_environment() {
+        let (_root, mut cmd) = command(RepipeOptions::default());
+        {
+            let _env = concourse_env(&[]);
+            let err = cmd.extract_meta("meta:\n  name: bosh\n").unwrap_err();
+            assert!(err.to_string().contains("Pipeline has no `meta.target`"), "{}", err);
+        }
+        let _env = concourse_env(&[("CONCOURSE_TARGET", "from-env"), ("CONCOURSE_URL", "https://ci.example.com")]);
+        cmd.extract_meta("meta:\n  name: bosh\n").unwrap();
+        assert_eq!((cmd.target.as_str(), cmd.pipeline.as_str()), ("from-env", "bosh"));
+        assert_eq!(cmd.meta.as_ref().and_then(|meta| meta.url.as_deref()), Some("https://ci.example.com"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_build_scripts_are_named_and_started_ones_stopped() {
+        let (_root, mut cmd) = command(RepipeOptions::default());
+        let scripts = cmd.base_dir.join("scripts");
+        fs::create_dir_all(&scripts).unwrap();
+        let script = |name: &str, body: &str| {
+            fs::write(scripts.join(name), body).unwrap();
+            fs::set_permissions(scripts.join(name), fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        script("build-test-jobs", "#!/bin/sh\nexit 3\n");
+        script("build-upstream-jobs", "#!/bin/sh\ntouch \"$0.ran\"\n");
+        let err = cmd.execute_build_scripts().unwrap_err().to_string();
+        assert!(err.starts_with("Build script failed: build-test-jobs"), "{}", err);
+        assert!(!scripts.join("build-upstream-jobs.ran").exists());
+
+        // The second script can't start, so the first is killed before it finishes
+        cmd.options.parallel_scripts = true;
+        script("build-test-jobs", "#!/bin/sh\nsleep 1\ntouch \"$0.ran\"\n");
+        script("build-upstream-jobs", "#!/no/such/interpreter\n");
+        let err = cmd.execute_build_scripts().unwrap_err().to_string();
+        assert_eq!(err, "Failed to start build-upstream-jobs");
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        assert!(!scripts.join("build-test-jobs.ran").exists());
+    }
+
+    #[test]
+    fn output_file_is_named_after_the_instanced_pipeline() {
+        let out = tempfile::tempdir().unwrap();
+        let (_root, mut cmd) = command(RepipeOptions {
+            output_dir: Some(out.path().join("artifacts")),
+            instance_vars: vec![(String::from("env"), String::from("us/east"))],
+            ..Default::default()
+        });
+        cmd.pipeline = String::from("bosh");
+        cmd.write_output_file("jobs: []\n").unwrap();
+        let path = out.path().join("artifacts").join("bosh_env_us_east.yml");
+        assert_eq!(fs::read_to_string(path).unwrap(), "jobs: []\n");
+    }
+
+    #[test]
+    fn ci_directory_is_found_next_to_or_above_the_kit_root() {
+        let root = tempfile::tempdir().unwrap();
+        let kit = root.path().join("kit");
+        fs::create_dir_all(&kit).unwrap();
+        let pick = |dir: &Path| RepipeCommand::pick_ci_directory(dir, true).map_err(|e| e.to_string());
+
+        let err = pick(&kit).unwrap_err();
+        assert!(err.starts_with("Could not find ci directory"), "{}", err);
+
+        fs::create_dir(root.path().join("ci")).unwrap();
+        assert_eq!(pick(&kit), Ok(root.path().join("ci")));
+        assert_eq!(RepipeCommand::ci_directory_candidates(&root.path().join("ci")), [root.path().join("ci")]);
+
+        fs::create_dir(kit.join("ci")).unwrap();
+        assert_eq!(RepipeCommand::ci_directory_candidates(&kit), [kit.join("ci"), root.path().join("ci")]);
+        {
+            // Prompts are off, so two candidates can't be settled
+            let _env = concourse_env(&[]);
+            let err = pick(&kit).unwrap_err();
+            assert!(err.starts_with("Found more than one ci directory"), "{}", err);
+        }
+
+        fs::remove_dir(root.path().join("ci")).unwrap();
+        assert_eq!(pick(&kit), Ok(kit.join("ci")));
+    }
+
+    #[test]
+    fn deploy_file_may_not_clobber_inputs() {
+        let (_root, mut cmd) = command(RepipeOptions::default());
+        fs::write(cmd.base_dir.join("notes.yml"), "a: 1\n").unwrap();
+        fs::write(cmd.base_dir.join(DEFAULT_DEPLOY_FILE), "stale: true\n").unwrap();
+        let git = |args: &[&str]| assert!(Command::new("git").arg("-C").arg(&cmd.base_dir).args(args).output().unwrap().status.success());
+        git(&["init", "-q"]);
+        git(&["add", "notes.yml"]);
+        cmd.settings_file = cmd.base_dir.join("prod.yml");
 
-        if let Err(e) = RepipeCommand::new(RepipeOptions::default()).and_then(|mut cmd| cmd.execute()) {
-            error!("Repipe failed: {}", e);
+        let mut check = |name: &str| {
+            cmd.options.deploy_file = Some(String::from(name));
+            cmd.check_deploy_file().map_err(|e| e.to_string())
+        };
+        assert_eq!(check(DEFAULT_DEPLOY_FILE), Ok(()));
+        for (name, reason) in [
+            ("../deploy.yml", "plain file name"),
+            ("pipeline/deploy.yml", "plain file name"),
+            ("pipeline", "pipeline inputs"),
+            ("settings-prod.yml", "pipeline inputs"),
+            ("prod.yml", "settings file"),
+            ("notes.yml", "tracked by git"),
+        ] {
+            let err = check(name).unwrap_err();
+            assert!(err.contains(reason), "{}: {}", name, err);
         }
     }
-}
\ No newline at end of file
+
+    #[cfg(unix)]
+    #[test]
+    fn since_matches_kits_by_checkout_not_name() {
+        let root = tempfile::tempdir().unwrap();
+        let real = root.path().canonicalize().unwrap();
+        for dir in ["checkouts/bosh-genesis-kit/ci", "vault/ci"] {
+            fs::create_dir_all(real.join(dir)).unwrap();
+        }
+        // The kit is reached through a symlink but git reports canonical paths
+        std::os::unix::fs::symlink(real.join("checkouts"), real.join("linked")).unwrap();
+        let mut bosh = Kit::load(&real.join("linked/bosh-genesis-kit")).unwrap();
+        bosh.name = String::from("bosh");
+        let kits = [bosh, Kit::load(&real.join("vault")).unwrap()];
+
+        let changed = [real.join("checkouts/bosh-genesis-kit/ci/settings.yml")];
+        assert_eq!(changed_kits(&kits, &changed), ["bosh"]);
+        let changed = [real.join("vault/ci/pipeline/base.yml"), real.join("bosh/ci/settings.yml")];
+        assert_eq!(changed_kits(&kits, &changed), ["vault"]);
+        assert!(changed_kits(&kits, &[real.join("README.md")]).is_empty());
+    }
+
+    #[test]
+    fn instance_vars_flow_into_the_fly_args() {
+        let _env = concourse_env(&[]);
+        let instance_vars = vec![(String::from("branch"), String::from("feature/x")), (String::from("env"), String::from("lab"))];
+        let (_root, mut cmd) = command(RepipeOptions { instance_vars, ..Default::default() });
+        cmd.extract_meta("meta:\n  target: ci\n  url: https://ci.example.com\n  name: bosh\n  team: main\n").unwrap();
+
+        let set = args(&cmd.set_pipeline_command("fly", false));
+        assert_eq!(set[set.len() - 4..], ["--instance-var", "branch=feature/x", "--instance-var", "env=lab"]);
+        assert_eq!(args(&cmd.pause_command("fly")),
+            ["--target", "ci", "unpause-pipeline", "--pipeline", "bosh/branch:feature/x,env:lab"]);
+        assert_eq!(args(&cmd.visibility_command("fly", false))[4], "bosh/branch:feature/x,env:lab");
+        assert_eq!(cmd.pipeline_url().unwrap(),
+            "https://ci.example.com/teams/main/pipelines/bosh?vars.branch=%22feature%2Fx%22&vars.env=%22lab%22");
+    }
+
+    #[test]
+    fn target_precedence_is_config_flag_meta_env() {
+        let settings: config::Config = serde_yaml::from_str("kits:\n  bosh: {target: pinned, team: ops}\n").unwrap();
+        let unconfigured = config::Config::default();
+        let with_target = "meta:\n  target: from-meta\n  name: bosh\n  team: meta-team\n";
+        let without_target = "meta:\n  name: bosh\n";
+        let cases = [
+            (&settings, Some("flagged"), with_target, ("pinned", MetaSource::Config), Some("ops")),
+            (&unconfigured, Some("flagged"), with_target, ("flagged", MetaSource::Flag), Some("meta-team")),
+            (&unconfigured, None, with_target, ("from-meta", MetaSource::Meta), Some("meta-team")),
+            (&unconfigured, None, without_target, ("from-env", MetaSource::Env), None),
+        ];
+        let _env = concourse_env(&[("CONCOURSE_TARGET", "from-env")]);
+        for (settings, flag, meta, (target, source), team) in cases {
+            let (_root, mut cmd) = command(RepipeOptions { target: flag.map(String::from), ..Default::default() });
+            let dump = cmd.resolve_meta_with(meta, settings).unwrap();
+            assert_eq!(cmd.target, target, "{:?} {}", flag, meta);
+            assert_eq!(dump.resolved["target"].source, source);
+            assert_eq!(cmd.meta.as_ref().unwrap().team.as_deref(), team);
+        }
+    }
+
+    #[test]
+    fn manifest_name_picks_the_kits_config_entry() {
+        let _env = concourse_env(&[]);
+        let base_dir = kits::test_kit_root().join("checkouts").join("bosh-genesis-kit").join("ci");
+        let mut cmd = command_in(base_dir, RepipeOptions::default());
+        assert_eq!(cmd.kit_name().as_deref(), Some("bosh"));
+
+        let settings: config::Config = serde_yaml::from_str("kits:\n  bosh: {target: pinned}\n  bosh-genesis-kit: {target: by-path}\n").unwrap();
+        cmd.resolve_meta_with("meta:\n  target: from-meta\n  name: bosh\n", &settings).unwrap();
+        assert_eq!(cmd.target, "pinned");
+
+        let (_root, cmd) = command(RepipeOptions::default());
+        assert_eq!(cmd.kit_name().as_deref(), Some("bosh"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks_see_the_resolved_kit_target_and_pipeline() {
+        let _env = concourse_env(&[]);
+        let (_root, mut cmd) = command(RepipeOptions { pipeline: Some(String::from("bosh-test")), ..Default::default() });
+        let hook = cmd.base_dir.join("hooks").join("pre-repipe");
+        fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        fs::write(&hook, "#!/bin/sh\nenv | grep '^GK_' | sort > \"$GK_CI_DIR/hook.env\"\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+        cmd.extract_meta("meta:\n  target: ci\n  name: bosh\n").unwrap();
+        cmd.run_hook("pre-repipe").unwrap();
+        let seen = fs::read_to_string(cmd.base_dir.join("hook.env")).unwrap();
+        assert_eq!(seen.lines().collect::<Vec<_>>(), [
+            format!("GK_CI_DIR={}", cmd.base_dir.display()),
+            String::from("GK_KIT=bosh"),
+            String::from("GK_PIPELINE=bosh-test"),
+            String::from("GK_TARGET=ci"),
+        ]);
+    }
+
+    #[test]
+    fn meta_dump_lists_each_field_with_its_source() {
+        let _env = concourse_env(&[("CONCOURSE_URL", "https://ci.example.com")]);
+        let (_root, mut cmd) = command(RepipeOptions { pipeline: Some(String::from("bosh-test")), ..Default::default() });
+        let dump = cmd.resolve_meta("meta:\n  target: ci\n  name: bosh\n").unwrap();
+        assert_eq!(serde_json::to_value(&dump).unwrap(), serde_json::json!({
+            "meta": { "target": "ci", "url": null, "team": null, "pipeline": null, "name": "bosh", "exposed": null },
+            "resolved": {
+                "pipeline": { "value": "bosh-test", "source": "flag" },
+                "target": { "value": "ci", "source": "meta" },
+                "team": { "value": null, "source": "unset" },
+                "url": { "value": "https://ci.example.com", "source": "env" },
+            },
+        }));
+    }
+
+    #[test]
+    fn meta_is_pruned_from_the_merged_pipeline() {
+        assert_eq!(config::Config::default().prune_keys(), ["meta"]);
+        let (_root, cmd) = command(RepipeOptions::default());
+        assert_eq!(cmd.prune_keys(), config::config().prune_keys());
+        let (_root, cmd) = command(RepipeOptions { prune: vec![String::from("meta"), String::from("pipeline")], ..Default::default() });
+        assert_eq!(cmd.prune_keys(), ["meta", "pipeline"]);
+
+        if find_tool("spruce", None).is_err() {
+            eprintln!("spruce isn't installed; skipping the merge itself");
+            return;
+        }
+        let merged = "meta: {kit: bosh}\npipeline: {name: bosh}\njobs: [{name: test-kit}]\n";
+        let pruned = yaml(&cmd.pruned_yaml(merged).unwrap());
+        assert!(pruned.get("meta").is_none() && pruned.get("pipeline").is_none(), "{:?}", pruned);
+        assert_eq!(pruned["jobs"][0]["name"], "test-kit");
+    }
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn pipeline_override_is_what_fly_sets() {
+        let _env = concourse_env(&[]);
+        let options = RepipeOptions { pipeline: Some(String::from("bosh-test")), fly_args: vec![String::from("--team=ops")], ..Default::default() };
+        let (_root, mut cmd) = command(options);
+        cmd.extract_meta("meta:\n  target: ci\n  name: bosh\n  team: main\n").unwrap();
+        assert_eq!((cmd.target.as_str(), cmd.pipeline.as_str()), ("ci", "bosh-test"));
+
+        let deploy_file = cmd.base_dir.join(DEFAULT_DEPLOY_FILE).to_string_lossy().into_owned();
+        assert_eq!(args(&cmd.set_pipeline_command("fly", true)),
+            ["--target", "ci", "set-pipeline", "--pipeline", "bosh-test", "--config", &deploy_file, "--non-interactive", "--team=ops"]);
+        assert_eq!(args(&cmd.pause_command("fly")), ["--target", "ci", "unpause-pipeline", "--pipeline", "bosh-test"]);
+        assert_eq!(args(&cmd.visibility_command("fly", true)), ["--target", "ci", "expose-pipeline", "--pipeline", "bosh-test"]);
+    }
+
+    #[test]
+    fn concourse_url_and_team_fill_in_for_minimal_meta() {
+        let _env = concourse_env(&[
+            ("CONCOURSE_TARGET", "ci"),
+            ("CONCOURSE_URL", "https://ci.example.com"),
+            ("CONCOURSE_TEAM", "ops"),
+        ]);
+        let (_root, mut cmd) = command(RepipeOptions { skip_flyrc_check: true, strict_target: true, ..Default::default() });
+        cmd.extract_meta("meta:\n  name: bosh\n").unwrap();
+        let meta = cmd.meta.clone().unwrap();
+        assert_eq!(cmd.target, "ci");
+        assert_eq!((meta.url.as_deref(), meta.team.as_deref()), (Some("https://ci.example.com"), Some("ops")));
+        assert_eq!(cmd.pipeline_url().as_deref(), Some("https://ci.example.com/teams/ops/pipelines/bosh"));
+        cmd.validate_target().unwrap();
+
+        // Meta still wins, and a strict check catches it disagreeing with the environment
+        cmd.extract_meta("meta:\n  name: bosh\n  team: main\n").unwrap();
+        assert_eq!(cmd.meta.as_ref().unwrap().team.as_deref(), Some("main"));
+        let err = cmd.validate_target().unwrap_err().to_string();
+        assert!(err.contains("meta.team is 'main' but the environment has 'ops'"), "{}", err);
+    }
+
+    #[test]
+    fn expose_flag_beats_meta_beats_hidden() {
+        let cases = [
+            (Some(true), Some(false), true),
+            (Some(false), Some(true), false),
+            (None, Some(true), true),
+            (None, Some(false), false),
+            (None, None, false),
+            (Some(true), None, true),
+        ];
+        for (flag, exposed, expected) in cases {
+            let (_root, mut cmd) = command(RepipeOptions { expose: flag, ..Default::default() });
+            cmd.meta = Some(PipelineMeta { exposed, ..Default::default() });
+            assert_eq!(cmd.resolve_expose(), expected, "flag {:?}, meta.exposed {:?}", flag, exposed);
+        }
+        let (_root, cmd) = command(RepipeOptions::default());
+        assert!(!cmd.resolve_expose());
+    }
+
+    #[test]
+    fn target_names_are_letters_digits_and_separators() {
+        for valid in ["ci", "genesis-ci", "prod_eu.2", "A1"] {
+            assert!(validate_target_name(valid).is_ok(), "{:?} was rejected", valid);
+        }
+        for (invalid, found) in [("my target", "' '"), ("ci/prod", "'/'"), ("ci:8080", "':'"), ("cï", "'ï'")] {
+            let err = validate_target_name(invalid).unwrap_err().to_string();
+            assert!(err.contains(&format!("found {}", found)), "{}", err);
+        }
+        assert!(validate_target_name("").unwrap_err().to_string().contains("empty"));
+    }
+
+    #[test]
+    fn redact_masks_whole_secret_key_segments() {
+        let mut config = yaml(r#"
+meta:
+  password: hunter2
+  api_key: abc123
+  private-key: |
+    -----BEGIN KEY-----
+  github:
+    token: ghp_123
+    access_token: ((github-token))
+  aws:
+    secret_access_key: s3cr3t
+  vault:
+    secret:
+      role: ci
+      paths: [one, two]
+  monkey: banana
+  keys: [a, b]
+  keyboard_layout: dvorak
+  tokenizer: words
+"#);
+        redact(&mut config, false);
+        assert_eq!(config, yaml(r#"
+meta:
+  password: "***"
+  api_key: "***"
+  private-key: "***"
+  github:
+    token: "***"
+    access_token: ((github-token))
+  aws:
+    secret_access_key: "***"
+  vault:
+    secret:
+      role: "***"
+      paths: ["***", "***"]
+  monkey: banana
+  keys: [a, b]
+  keyboard_layout: dvorak
+  tokenizer: words
+"#));
+    }
+}
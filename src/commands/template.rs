@@ -1,56 +1,289 @@
-use anyhow::Result;
-use dialoguer::{Input, Select};
+use anyhow::{bail, Context, Result};
+use dialoguer::Input;
 use semver::Version;
-use std::{thread, time::Duration};
+use log::warn;
+use regex::Regex;
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use walkdir::WalkDir;
 use crate::{
-    ui::GenesisKitUI,
-    constants::AVAILABLE_KITS,
+    say,
+    ui::{prompt_error, require_terminal, GenesisKitUI},
+    process::RunExt,
+    ui::diff::render_diff,
     ui::styles::*,
     ui::progress::create_progress_bar,
 };
 use console::style;
 
+/// The kit's own regeneration tool, run from the kit checkout with the new
+/// template version as its only argument (and in `$TEMPLATE_VERSION`).
+const REGENERATE_SCRIPT: &str = "ci/scripts/regenerate";
+
+/// Files besides `kit.yml` that hold nothing but the template version; each is
+/// bumped along with it when present.
+const TEMPLATE_VERSION_FILES: &[&str] = &["TEMPLATE_VERSION", "ci/TEMPLATE_VERSION"];
+
+/// A checked template version bump for one kit.
+#[derive(Debug, Clone)]
+pub struct TemplateUpdate {
+    pub kit: String,
+    pub previous: Version,
+    pub new: Version,
+}
+
+/// Validates moving `kit` from template version `current` to `new`.
+pub fn update_template_version(kit: &str, current: &str, new: &str) -> Result<TemplateUpdate> {
+    let previous = Version::parse(current)
+        .with_context(|| format!("Current template version '{}' is not a semantic version", current))?;
+    let new = Version::parse(new)
+        .with_context(|| format!("'{}' is not a valid semantic version (e.g., 2.1.0)", new))?;
+    Ok(TemplateUpdate { kit: kit.to_string(), previous, new })
+}
+
+/// New contents for one file, staged before anything is written.
+#[derive(Debug)]
+struct FileEdit {
+    path: PathBuf,
+    contents: String,
+}
+
+/// The edits bumping `kit_dir` from template version `previous` to `new`:
+/// `template_version` in `kit.yml`, and any [`TEMPLATE_VERSION_FILES`].
+fn template_version_edits(kit_dir: &Path, previous: &Version, new: &Version) -> Result<Vec<FileEdit>> {
+    let kit_file = kit_dir.join("kit.yml");
+    let contents = fs::read_to_string(&kit_file)
+        .with_context(|| format!("Failed to read {}", kit_file.display()))?;
+    // Edit the line in place so the rest of kit.yml keeps its layout and comments
+    let line = Regex::new(r#"(?m)^(\s*template_version:\s*["']?)[^"'\s#]+"#).unwrap();
+    if !line.is_match(&contents) {
+        bail!("{} has no template_version line to update", kit_file.display());
+    }
+    let updated = line.replacen(&contents, 1, |caps: &regex::Captures| format!("{}{}", &caps[1], new));
+    let mut edits = vec![FileEdit { path: kit_file, contents: updated.into_owned() }];
+
+    for name in TEMPLATE_VERSION_FILES {
+        let path = kit_dir.join(name);
+        let Ok(contents) = fs::read_to_string(&path) else { continue };
+        if contents.trim() == previous.to_string() {
+            edits.push(FileEdit { path, contents: format!("{}\n", new) });
+        } else {
+            warn!("Leaving {} alone: it holds '{}', not {}", path.display(), contents.trim(), previous);
+        }
+    }
+    Ok(edits)
+}
+
+/// Writes all of `edits` or none of them. Each is staged in a temp file next to
+/// its target, and only once every one is staged are they renamed into place;
+/// a rename that fails puts the files already replaced back as they were.
+fn apply_edits(edits: &[FileEdit]) -> Result<()> {
+    apply_edits_with(edits, |file, path| file.persist(path).map(drop).map_err(|e| e.error))
+}
+
+/// [`apply_edits`] with the final rename done by `persist`.
+fn apply_edits_with(
+    edits: &[FileEdit],
+    mut persist: impl FnMut(tempfile::NamedTempFile, &Path) -> std::io::Result<()>,
+) -> Result<()> {
+    let mut staged = Vec::with_capacity(edits.len());
+    for edit in edits {
+        let dir = edit.path.parent().context("File to update has no parent directory")?;
+        let original = fs::read(&edit.path)
+            .with_context(|| format!("Failed to read {}", edit.path.display()))?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)
+            .with_context(|| format!("Failed to stage an update in {}", dir.display()))?;
+        file.write_all(edit.contents.as_bytes())
+            .with_context(|| format!("Failed to stage the update to {}", edit.path.display()))?;
+        fs::set_permissions(file.path(), fs::metadata(&edit.path)?.permissions())?;
+        staged.push((file, edit, original));
+    }
+
+    let mut replaced: Vec<(&Path, Vec<u8>)> = Vec::new();
+    for (file, edit, original) in staged {
+        if let Err(e) = persist(file, &edit.path) {
+            for (path, contents) in replaced.iter().rev() {
+                if let Err(restore) = fs::write(path, contents) {
+                    warn!("Failed to restore {}: {}", path.display(), restore);
+                }
+            }
+            return Err(e).with_context(|| format!("Failed to update {}; no files were changed", edit.path.display()));
+        }
+        replaced.push((&edit.path, original));
+    }
+    Ok(())
+}
+
+/// Every file under `root`, relative to it, leaving out `.git`.
+fn tree_files(root: &Path) -> BTreeSet<PathBuf> {
+    WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git")
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.path().strip_prefix(root).ok().map(Path::to_path_buf))
+        .collect()
+}
+
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    for file in tree_files(from) {
+        let dest = to.join(&file);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from.join(&file), &dest)
+            .with_context(|| format!("Failed to copy {}", file.display()))?;
+    }
+    Ok(())
+}
+
+/// Runs the kit's regeneration against `version` in a scratch copy of
+/// `kit_dir` and returns a diff per changed file. `kit_dir` is never touched.
+pub fn template_diff(kit_dir: &Path, version: &Version) -> Result<Vec<String>> {
+    if !kit_dir.join(REGENERATE_SCRIPT).is_file() {
+        bail!("{} has no {}, so its files can't be regenerated for a preview",
+            kit_dir.display(), REGENERATE_SCRIPT);
+    }
+
+    let sandbox = tempfile::tempdir().context("Failed to create a scratch directory")?;
+    copy_tree(kit_dir, sandbox.path())?;
+
+    let output = Command::new(sandbox.path().join(REGENERATE_SCRIPT))
+        .arg(version.to_string())
+        .env("TEMPLATE_VERSION", version.to_string())
+        .current_dir(sandbox.path())
+        .run_output()
+        .with_context(|| format!("Failed to run {}", REGENERATE_SCRIPT))?;
+    if !output.status.success() {
+        bail!("{} failed: {}", REGENERATE_SCRIPT, String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let before = tree_files(kit_dir);
+    let after = tree_files(sandbox.path());
+    let mut diffs = Vec::new();
+    for file in before.union(&after) {
+        let old = fs::read(kit_dir.join(file)).unwrap_or_default();
+        let new = fs::read(sandbox.path().join(file)).unwrap_or_default();
+        if old == new {
+            continue;
+        }
+        let label = file.display().to_string();
+        match (String::from_utf8(old), String::from_utf8(new)) {
+            (Ok(old), Ok(new)) => diffs.extend(render_diff(&old, &new, &label, &label)),
+            _ => diffs.push(format!("Binary file {} differs", label)),
+        }
+    }
+    Ok(diffs)
+}
+
 impl GenesisKitUI {
+    /// `gk template diff`: previews what a template bump would change, without changing anything.
+    pub fn preview_template_version(&self, kit: Option<&str>, version: &str) -> Result<()> {
+        let version = Version::parse(version)
+            .with_context(|| format!("'{}' is not a valid semantic version (e.g., 2.1.0)", version))?;
+        let kit = self.select_kit(kit, "Select kit to preview")?;
+        let kit_dir = kit.path.parent().context("Kit ci directory has no parent")?;
+
+        say!("\n{}\n", heading(&format!("🔍 TEMPLATE {} PREVIEW FOR {}", version, kit.name)));
+        let diffs = template_diff(kit_dir, &version)?;
+        if diffs.is_empty() {
+            println!("{}", info("No files would change"));
+            return Ok(());
+        }
+        for diff in &diffs {
+            println!("{}\n", diff);
+        }
+        println!("{}", info(&format!("{} file{} would change", diffs.len(), if diffs.len() == 1 { "" } else { "s" })));
+        Ok(())
+    }
+
     pub async fn manage_template_version(&self) -> Result<()> {
-        println!("\n{}\n", heading("📋 TEMPLATE VERSION MANAGEMENT"));
+        say!("\n{}\n", heading("📋 TEMPLATE VERSION MANAGEMENT"));
 
-        let kit = Select::with_theme(&self.theme)
-            .with_prompt(&param("Select kit to update"))
-            .items(AVAILABLE_KITS)
-            .interact()?;
+        let kit = self.select_kit(None, "Select kit to update")?;
 
-        let current_version = "2.0.0"; // This would be fetched from the kit
-        println!("{} {}", info("Current template version:"), style(current_version).green());
+        let current_version = kit.template_version.as_ref()
+            .with_context(|| format!("{} has no template_version in its kit.yml", kit.name))?
+            .to_string();
+        println!("{} {}", info("Current template version:"), style(&current_version).fg(palette().success));
         
+        let hint = "`gk template diff --kit <kit> --version <version>` to preview a bump";
+        require_terminal(hint)?;
         let new_version: String = Input::with_theme(&self.theme)
-            .with_prompt(&param("Enter new template version"))
+            .with_prompt(param("Enter new template version"))
             .validate_with(|input: &String| -> Result<(), &str> {
                 Version::parse(input).map_err(|_| "Please enter a valid semantic version (e.g., 2.1.0)")?;
                 Ok(())
             })
-            .interact_text()?;
+            .interact_text()
+            .map_err(|e| prompt_error(e, hint))?;
+        let update = update_template_version(&kit.name, &current_version, &new_version)?;
 
-        println!("\n{}", heading("🔄 UPDATING TEMPLATE VERSION"));
+        say!("\n{}", heading("🔄 UPDATING TEMPLATE VERSION"));
         
-        let pb = create_progress_bar(&self.multi_progress, 100, "Updating template version");
-        for i in 0..100 {
-            pb.inc(1);
-            thread::sleep(Duration::from_millis(20));
-            
-            match i {
-                30 => pb.set_message("Validating template compatibility..."),
-                60 => pb.set_message("Updating dependencies..."),
-                90 => pb.set_message("Regenerating configurations..."),
-                _ => {}
-            }
-        }
+        let kit_dir = kit.path.parent().context("Kit ci directory has no parent")?;
+        let edits = template_version_edits(kit_dir, &update.previous, &update.new)?;
+        let pb = create_progress_bar(&self.multi_progress, 1, "Updating template version");
+        apply_edits(&edits)?;
+        pb.inc(1);
         pb.finish_with_message("✓ Template version updated successfully");
 
-        println!("\n{}", heading("📊 UPDATE SUMMARY"));
-        println!("Kit:              {}", style(AVAILABLE_KITS[kit]).green());
-        println!("Previous Version: {}", style(current_version).yellow());
-        println!("New Version:      {}", style(new_version).green());
+        say!("\n{}", heading("📊 UPDATE SUMMARY"));
+        println!("Kit:              {}", style(&update.kit).fg(palette().success));
+        println!("Previous Version: {}", style(&update.previous).fg(palette().warning));
+        println!("New Version:      {}", style(&update.new).fg(palette().success));
+        println!("Files Changed:");
+        for edit in &edits {
+            let path = edit.path.strip_prefix(kit_dir).unwrap_or(&edit.path);
+            println!("  {}", style(path.display()).fg(palette().info));
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_write_rolls_back_earlier_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (dir.path().join("kit.yml"), dir.path().join("base.yml"));
+        fs::write(&first, "template_version: 1.0.0\n").unwrap();
+        fs::write(&second, "version: 1.0.0\n").unwrap();
+        let edits = [
+            FileEdit { path: first.clone(), contents: String::from("template_version: 2.0.0\n") },
+            FileEdit { path: second.clone(), contents: String::from("version: 2.0.0\n") },
+        ];
+
+        let mut calls = 0;
+        let result = apply_edits_with(&edits, |file, path| {
+            calls += 1;
+            if calls == 2 {
+                return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "read-only"));
+            }
+            file.persist(path).map(drop).map_err(|e| e.error)
+        });
+
+        let err = result.unwrap_err();
+        assert!(format!("{:#}", err).contains("no files were changed"));
+        assert_eq!(fs::read_to_string(&first).unwrap(), "template_version: 1.0.0\n");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "version: 1.0.0\n");
+    }
+
+    #[test]
+    fn apply_edits_writes_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kit.yml");
+        fs::write(&path, "old\n").unwrap();
+        apply_edits(&[FileEdit { path: path.clone(), contents: String::from("new\n") }]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+    }
+}
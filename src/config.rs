@@ -0,0 +1,120 @@
+// src/config.rs
+use log::warn;
+use serde::Deserialize;
+use std::{collections::HashMap, env, fs, path::PathBuf, sync::OnceLock};
+use crate::ui::styles::Theme;
+
+const CONFIG_FILE: &str = "config.yml";
+const DEFAULT_PIPELINE_NAME_TEMPLATE: &str = "genesis-kit-{kit}";
+const DEFAULT_JOB_TEMPLATE: &str = "{pipeline}/test-kit";
+const DEFAULT_PRUNE: &[&str] = &["meta"];
+const DEFAULT_SETTINGS_DIR: &str = "settings";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// User settings from `$GKM_CONFIG`, or `config.yml` in the gkm config dir
+/// (`~/.config/gkm` on Linux). Every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Default pipeline name for kits whose meta doesn't set one; `{kit}` is
+    /// replaced with the kit name.
+    pub pipeline_name_template: Option<String>,
+    /// The job status, trigger, watch and logs act on, as `{pipeline}/<job>`;
+    /// `{pipeline}` and `{kit}` are filled in.
+    pub job_template: Option<String>,
+    /// Top-level keys repipe prunes from the merged pipeline; `meta` when unset.
+    pub prune: Option<Vec<String>>,
+    /// Subdirectory of `ci/` holding `<env>.yml`/`<target>.yml` settings; `settings` when unset.
+    pub settings_dir: Option<String>,
+    /// Color palette: `dark` (the default), `light`, or `none` for no color.
+    pub theme: Theme,
+    /// Per-kit overrides, keyed by kit name.
+    pub kits: HashMap<String, KitConfig>,
+}
+
+/// Settings for one kit under `kits:`; they win over flags and pipeline meta.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct KitConfig {
+    pub target: Option<String>,
+    pub team: Option<String>,
+}
+
+impl Config {
+    fn path() -> Option<PathBuf> {
+        env::var_os("GKM_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| dirs::config_dir().map(|dir| dir.join("gkm").join(CONFIG_FILE)))
+    }
+
+    /// Reads the config file, treating a missing one as empty and warning about a broken one.
+    fn load() -> Self {
+        let Some(path) = Self::path().filter(|p| p.is_file()) else {
+            return Self::default();
+        };
+        let parsed = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| Ok(serde_yaml::from_str::<Config>(&contents)?));
+        parsed.unwrap_or_else(|e| {
+            warn!("Ignoring unreadable config {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    pub fn kit(&self, kit: &str) -> Option<&KitConfig> {
+        self.kits.get(kit)
+    }
+
+    pub fn job_ref(&self, kit: &str, pipeline: &str) -> String {
+        self.job_template
+            .as_deref()
+            .unwrap_or(DEFAULT_JOB_TEMPLATE)
+            .replace("{pipeline}", pipeline)
+            .replace("{kit}", kit)
+    }
+
+    pub fn prune_keys(&self) -> Vec<String> {
+        self.prune.clone().unwrap_or_else(|| DEFAULT_PRUNE.iter().map(|key| key.to_string()).collect())
+    }
+
+    pub fn settings_dir(&self) -> &str {
+        self.settings_dir.as_deref().unwrap_or(DEFAULT_SETTINGS_DIR)
+    }
+
+    pub fn pipeline_name(&self, kit: &str) -> String {
+        self.pipeline_name_template
+            .as_deref()
+            .unwrap_or(DEFAULT_PIPELINE_NAME_TEMPLATE)
+            .replace("{kit}", kit)
+    }
+}
+
+/// The config, loaded on first use.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::load)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn pipeline_name_template_fills_in_the_kit() {
+        assert_eq!(Config::default().pipeline_name("bosh"), "genesis-kit-bosh");
+        assert_eq!(parsed("pipeline_name_template: ci-{kit}\n").pipeline_name("bosh"), "ci-bosh");
+        assert_eq!(parsed("pipeline_name_template: \"{kit}-pipeline-{kit}\"\n").pipeline_name("vault"), "vault-pipeline-vault");
+        assert_eq!(parsed("pipeline_name_template: shared\n").pipeline_name("bosh"), "shared");
+    }
+
+    #[test]
+    fn job_template_fills_in_pipeline_and_kit() {
+        assert_eq!(Config::default().job_ref("bosh", "genesis-kit-bosh"), "genesis-kit-bosh/test-kit");
+        let config = parsed("job_template: \"{pipeline}/test-{kit}\"\n");
+        assert_eq!(config.job_ref("bosh", "ci-bosh"), "ci-bosh/test-bosh");
+    }
+}
@@ -5,23 +5,14 @@ pub const LOGO: &str = r#"
 ██║   ██║██╔═██╗ ██║╚██╔╝██║
 ╚██████╔╝██║  ██╗██║ ╚═╝ ██║
  ╚═════╝ ╚═╝  ╚═╝╚═╝     ╚═╝
-    Concept-0.0.1-alpha
 "#;
 
-pub const AVAILABLE_KITS: &[&str] = &["shield-v2", "vault-v2", "bosh-v2", "concourse-v6"];
-pub const ENVIRONMENTS: &[&str] = &["sandbox", "dev", "staging", "prod"];
+/// Release version, shown on the welcome banner's "Version" line.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-// src/types.rs
-use tabled::Tabled;
+/// fly target the ci commands run against.
+pub const CI_TARGET: &str = "genesis-kits";
 
-#[derive(Debug, Tabled)]
-pub struct KitStatus {
-    #[tabled(rename = "Kit Name")]
-    pub name: String,
-    #[tabled(rename = "Version")]
-    pub version: String,
-    #[tabled(rename = "Template Version")]
-    pub template_version: String,
-    #[tabled(rename = "CI Status")]
-    pub ci_status: String,
-}
\ No newline at end of file
+pub const AVAILABLE_KITS: &[&str] = &["shield-v2", "vault-v2", "bosh-v2", "concourse-v6"];
+/// Values accepted by `repipe --env`.
+pub const ENVIRONMENTS: &[&str] = &["sandbox", "dev", "staging", "prod"];
@@ -0,0 +1,402 @@
+// src/fly.rs
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{collections::VecDeque, path::Path, process::Stdio};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    process::Command as AsyncCommand,
+};
+use crate::process::{self, AsyncRunExt};
+
+/// What a fly invocation produced. Plain data rather than `std::process::Output`
+/// so test doubles can build one without a real exit status.
+#[derive(Debug, Clone, Default)]
+pub struct FlyOutput {
+    pub success: bool,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl From<std::process::Output> for FlyOutput {
+    fn from(output: std::process::Output) -> Self {
+        FlyOutput { success: output.status.success(), stdout: output.stdout, stderr: output.stderr }
+    }
+}
+
+impl FlyOutput {
+    pub fn stdout_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    pub fn stderr_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+/// Parses fly's `--json` output, skipping any warning lines fly prints
+/// before the JSON itself (everything up to the first line opening with `[` or `{`).
+pub fn parse_fly_json(bytes: &[u8]) -> Result<Value> {
+    let raw = String::from_utf8_lossy(bytes);
+    let start = raw.split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let at = *offset;
+            *offset += line.len();
+            Some((at, line))
+        })
+        .find(|(_, line)| line.trim_start().starts_with(['[', '{']))
+        .map_or(raw.len(), |(at, _)| at);
+
+    serde_json::from_str(&raw[start..])
+        .with_context(|| format!("fly returned output that isn't JSON:\n{}", raw.trim()))
+}
+
+/// Flags gk passes to `fly set-pipeline` itself.
+pub const SET_PIPELINE_FLAGS: &[&str] = &["-t", "--target", "-p", "--pipeline", "-c", "--config", "-n", "--non-interactive"];
+/// Flags gk passes to `fly trigger-job` itself.
+pub const TRIGGER_JOB_FLAGS: &[&str] = &["-t", "--target", "-j", "--job", "-w", "--watch"];
+
+/// Rejects passthrough args (after `--`) that repeat a flag gk already sets.
+pub fn check_extra_args(extra: &[String], taken: &[&str]) -> Result<()> {
+    for arg in extra {
+        let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+        if taken.contains(&flag) {
+            anyhow::bail!("'{}' after -- conflicts with a flag gk already passes to fly", arg);
+        }
+    }
+    Ok(())
+}
+
+/// The fly command that makes a pipeline public or private.
+pub fn visibility_verb(exposed: bool) -> &'static str {
+    if exposed { "expose-pipeline" } else { "hide-pipeline" }
+}
+
+/// The fly operations the ci commands rely on.
+#[async_trait]
+pub trait FlyClient: Send + Sync {
+    /// The fly target calls go to
+    fn target(&self) -> &str;
+    /// The same client pointed at another target and team
+    fn scoped(&self, target: &str, team: Option<String>) -> Box<dyn FlyClient>;
+    /// `fly builds -j <job> --count <count> --json`
+    async fn builds(&self, job: &str, count: u32) -> Result<FlyOutput>;
+    /// `fly trigger-job -j <job> [--watch]`
+    async fn trigger_job(&self, job: &str, watch: bool) -> Result<FlyOutput>;
+    /// `fly trigger-job -j <job> --watch <extra>`, streamed like [`FlyClient::watch_live`]
+    async fn trigger_live(&self, job: &str, tail: Option<usize>, extra: &[String]) -> Result<FlyOutput>;
+    /// `fly watch -j <job> -b <build>`, with stdout streamed to the terminal, or
+    /// given `tail`, only its last `tail` lines kept in `stdout`
+    async fn watch_live(&self, job: &str, build: &str, tail: Option<usize>) -> Result<FlyOutput>;
+    /// `fly get-pipeline -p <pipeline>`
+    async fn get_pipeline(&self, pipeline: &str) -> Result<FlyOutput>;
+    /// `fly set-pipeline -p <pipeline> -c <config> --non-interactive <extra>`
+    async fn set_pipeline(&self, pipeline: &str, config: &Path, extra: &[String]) -> Result<FlyOutput>;
+    /// `fly configure -j <kit> --json`, which reports the kit's version info
+    async fn configure(&self, kit: &str) -> Result<FlyOutput>;
+    /// `fly pause-pipeline`/`unpause-pipeline -p <pipeline>`
+    async fn set_paused(&self, pipeline: &str, paused: bool) -> Result<FlyOutput>;
+    /// `fly expose-pipeline`/`hide-pipeline -p <pipeline>`
+    async fn set_exposed(&self, pipeline: &str, exposed: bool) -> Result<FlyOutput>;
+    /// `fly jobs -p <pipeline> --json`
+    async fn jobs(&self, pipeline: &str) -> Result<FlyOutput>;
+    /// `fly pipelines --json`
+    async fn pipelines(&self) -> Result<FlyOutput>;
+    /// `fly resources -p <pipeline> --json`
+    async fn resources(&self, pipeline: &str) -> Result<FlyOutput>;
+}
+
+/// Shells out to the `fly` binary against one target, optionally scoped to a team.
+pub struct ProcessFlyClient {
+    target: String,
+    team: Option<String>,
+}
+
+impl ProcessFlyClient {
+    pub fn new(target: impl Into<String>, team: Option<String>) -> Self {
+        Self { target: target.into(), team }
+    }
+
+    fn command(&self, args: &[&str], with_team: bool) -> AsyncCommand {
+        let mut cmd = AsyncCommand::new("fly");
+        cmd.args(["-t", &self.target]).args(args);
+        if let (true, Some(team)) = (with_team, &self.team) {
+            cmd.args(["--team", team]);
+        }
+        cmd
+    }
+
+    async fn run(&self, args: &[&str], with_team: bool) -> Result<FlyOutput> {
+        let output = self.command(args, with_team)
+            .run_output()
+            .await
+            .with_context(|| format!("Failed to run fly {}", args.first().copied().unwrap_or_default()))?;
+        Ok(output.into())
+    }
+
+    /// [`Self::run`] for calls that change the pipeline; `--dry-run` only prints them.
+    async fn change(&self, args: &[&str], with_team: bool) -> Result<FlyOutput> {
+        let output = self.command(args, with_team)
+            .run_change()
+            .await
+            .with_context(|| format!("Failed to run fly {}", args.first().copied().unwrap_or_default()))?;
+        Ok(output.into())
+    }
+
+    /// Runs a long-lived fly command without buffering all of its output: stdout
+    /// goes straight to the terminal, or with `tail` only the last `tail` lines
+    /// are held. Stderr is captured so errors can still be reported. Commands
+    /// that `change` something are only printed under `--dry-run`.
+    async fn stream(&self, args: &[&str], tail: Option<usize>, change: bool) -> Result<FlyOutput> {
+        let name = args.first().copied().unwrap_or_default();
+        let mut cmd = self.command(args, true);
+        if change && process::skip_for_dry_run(cmd.as_std()) {
+            return Ok(FlyOutput { success: true, ..Default::default() });
+        }
+        cmd.stderr(Stdio::piped());
+        if tail.is_some() {
+            cmd.stdout(Stdio::piped());
+        }
+        let mut child = cmd.run_spawn()
+            .with_context(|| format!("Failed to run fly {}", name))?;
+
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let keep_tail = async {
+            let mut lines = VecDeque::new();
+            if let (Some(stdout), Some(limit)) = (stdout, tail) {
+                let mut reader = BufReader::new(stdout).split(b'\n');
+                while let Some(line) = reader.next_segment().await? {
+                    lines.push_back(line);
+                    if lines.len() > limit {
+                        lines.pop_front();
+                    }
+                }
+            }
+            std::io::Result::Ok(lines)
+        };
+        let read_stderr = async {
+            let mut buf = Vec::new();
+            if let Some(mut stderr) = stderr {
+                stderr.read_to_end(&mut buf).await?;
+            }
+            std::io::Result::Ok(buf)
+        };
+        let (lines, stderr) = tokio::try_join!(keep_tail, read_stderr)
+            .with_context(|| format!("Failed to read fly {} output", name))?;
+        let status = child.wait()
+            .await
+            .with_context(|| format!("Failed to run fly {}", name))?;
+
+        let mut stdout = Vec::new();
+        for line in lines {
+            stdout.extend(line);
+            stdout.push(b'\n');
+        }
+        Ok(FlyOutput { success: status.success(), stdout, stderr })
+    }
+}
+
+#[async_trait]
+impl FlyClient for ProcessFlyClient {
+    fn target(&self) -> &str {
+        &self.target
+    }
+
+    fn scoped(&self, target: &str, team: Option<String>) -> Box<dyn FlyClient> {
+        Box::new(ProcessFlyClient::new(target, team))
+    }
+
+    async fn builds(&self, job: &str, count: u32) -> Result<FlyOutput> {
+        self.run(&["builds", "-j", job, &format!("--count={}", count), "--json"], true).await
+    }
+
+    async fn trigger_job(&self, job: &str, watch: bool) -> Result<FlyOutput> {
+        let mut args = vec!["trigger-job", "-j", job];
+        if watch {
+            args.push("--watch");
+        }
+        self.change(&args, true).await
+    }
+
+    async fn trigger_live(&self, job: &str, tail: Option<usize>, extra: &[String]) -> Result<FlyOutput> {
+        let mut args = vec!["trigger-job", "-j", job, "--watch"];
+        args.extend(extra.iter().map(String::as_str));
+        self.stream(&args, tail, true).await
+    }
+
+    async fn watch_live(&self, job: &str, build: &str, tail: Option<usize>) -> Result<FlyOutput> {
+        self.stream(&["watch", "-j", job, "-b", build], tail, false).await
+    }
+
+    async fn get_pipeline(&self, pipeline: &str) -> Result<FlyOutput> {
+        self.run(&["get-pipeline", "-p", pipeline], true).await
+    }
+
+    async fn set_pipeline(&self, pipeline: &str, config: &Path, extra: &[String]) -> Result<FlyOutput> {
+        let config = config.to_string_lossy();
+        let mut args = vec!["set-pipeline", "-p", pipeline, "-c", &config, "--non-interactive"];
+        args.extend(extra.iter().map(String::as_str));
+        self.change(&args, true).await
+    }
+
+    async fn configure(&self, kit: &str) -> Result<FlyOutput> {
+        self.run(&["configure", "-j", kit, "--json"], false).await
+    }
+
+    async fn set_paused(&self, pipeline: &str, paused: bool) -> Result<FlyOutput> {
+        let verb = if paused { "pause-pipeline" } else { "unpause-pipeline" };
+        self.change(&[verb, "-p", pipeline], true).await
+    }
+
+    async fn set_exposed(&self, pipeline: &str, exposed: bool) -> Result<FlyOutput> {
+        self.change(&[visibility_verb(exposed), "-p", pipeline], true).await
+    }
+
+    async fn jobs(&self, pipeline: &str) -> Result<FlyOutput> {
+        self.run(&["jobs", "-p", pipeline, "--json"], true).await
+    }
+
+    async fn pipelines(&self) -> Result<FlyOutput> {
+        self.run(&["pipelines", "--json"], false).await
+    }
+
+    async fn resources(&self, pipeline: &str) -> Result<FlyOutput> {
+        self.run(&["resources", "-p", pipeline, "--json"], true).await
+    }
+}
+
+/// A [`FlyClient`] that answers from canned output instead of running fly.
+/// Reads it has no answer for fail with a "not found" stderr, as fly does for
+/// pipelines that were never set; changes always succeed.
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MockFlyClient {
+    pub target: String,
+    /// `builds` output by job
+    pub builds: std::collections::HashMap<String, FlyOutput>,
+    /// `get-pipeline` output by pipeline
+    pub pipelines_config: std::collections::HashMap<String, FlyOutput>,
+    /// `configure` output by kit
+    pub configure: std::collections::HashMap<String, FlyOutput>,
+    pub pipelines: Option<FlyOutput>,
+    /// Jobs `builds` was called for, in call order, shared with scoped clones
+    pub builds_calls: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    /// The pipeline or job of every call that names one, as `<call> <name>`
+    pub pipeline_calls: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl MockFlyClient {
+    pub fn new(target: &str) -> Self {
+        MockFlyClient { target: target.to_string(), ..Default::default() }
+    }
+
+    /// A successful call that printed `stdout`.
+    pub fn ok(stdout: &str) -> FlyOutput {
+        FlyOutput { success: true, stdout: stdout.as_bytes().to_vec(), stderr: Vec::new() }
+    }
+
+    /// A failed call that printed `stderr`.
+    pub fn failed(stderr: &str) -> FlyOutput {
+        FlyOutput { success: false, stdout: Vec::new(), stderr: stderr.as_bytes().to_vec() }
+    }
+
+    fn answer(canned: Option<&FlyOutput>) -> Result<FlyOutput> {
+        Ok(canned.cloned().unwrap_or_else(|| Self::failed("error: resource not found")))
+    }
+
+    fn record(&self, call: &str, name: &str) {
+        self.pipeline_calls.lock().unwrap().push(format!("{} {}", call, name));
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl FlyClient for MockFlyClient {
+    fn target(&self) -> &str {
+        &self.target
+    }
+
+    fn scoped(&self, target: &str, _team: Option<String>) -> Box<dyn FlyClient> {
+        Box::new(MockFlyClient { target: target.to_string(), ..self.clone() })
+    }
+
+    async fn builds(&self, job: &str, _count: u32) -> Result<FlyOutput> {
+        self.builds_calls.lock().unwrap().push(job.to_string());
+        self.record("builds", job);
+        Self::answer(self.builds.get(job))
+    }
+
+    async fn trigger_job(&self, job: &str, _watch: bool) -> Result<FlyOutput> {
+        self.record("trigger-job", job);
+        Ok(Self::ok(""))
+    }
+
+    async fn trigger_live(&self, job: &str, _tail: Option<usize>, _extra: &[String]) -> Result<FlyOutput> {
+        self.record("trigger-job", job);
+        Ok(Self::ok(""))
+    }
+
+    async fn watch_live(&self, job: &str, _build: &str, _tail: Option<usize>) -> Result<FlyOutput> {
+        self.record("watch", job);
+        Self::answer(None)
+    }
+
+    async fn get_pipeline(&self, pipeline: &str) -> Result<FlyOutput> {
+        self.record("get-pipeline", pipeline);
+        Self::answer(self.pipelines_config.get(pipeline))
+    }
+
+    async fn set_pipeline(&self, pipeline: &str, _config: &Path, _extra: &[String]) -> Result<FlyOutput> {
+        self.record("set-pipeline", pipeline);
+        Ok(Self::ok(""))
+    }
+
+    async fn configure(&self, kit: &str) -> Result<FlyOutput> {
+        Self::answer(self.configure.get(kit))
+    }
+
+    async fn set_paused(&self, pipeline: &str, paused: bool) -> Result<FlyOutput> {
+        self.record(if paused { "pause-pipeline" } else { "unpause-pipeline" }, pipeline);
+        Ok(Self::ok(""))
+    }
+
+    async fn set_exposed(&self, pipeline: &str, exposed: bool) -> Result<FlyOutput> {
+        self.record(visibility_verb(exposed), pipeline);
+        Ok(Self::ok(""))
+    }
+
+    async fn jobs(&self, pipeline: &str) -> Result<FlyOutput> {
+        self.record("jobs", pipeline);
+        Self::answer(None)
+    }
+
+    async fn pipelines(&self) -> Result<FlyOutput> {
+        Self::answer(self.pipelines.as_ref())
+    }
+
+    async fn resources(&self, pipeline: &str) -> Result<FlyOutput> {
+        self.record("resources", pipeline);
+        Self::answer(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fly_json_skips_leading_warnings() {
+        let output = b"WARNING:\n\nfly version (7.9.0) is out of sync with the target (7.11.2). to sync up, run the following:\n\n    fly -t ci sync\n\n[{\"id\": 1, \"status\": \"succeeded\"}]\n";
+        assert_eq!(parse_fly_json(output).unwrap(), serde_json::json!([{ "id": 1, "status": "succeeded" }]));
+        assert_eq!(parse_fly_json(b"  {\"name\": \"bosh\"}").unwrap()["name"], "bosh");
+    }
+
+    #[test]
+    fn parse_fly_json_reports_output_without_json() {
+        let err = parse_fly_json(b"error: unknown target: ci\n").unwrap_err();
+        assert!(err.to_string().contains("unknown target: ci"), "{}", err);
+        assert!(parse_fly_json(b"").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+// src/flyrc.rs
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::{collections::BTreeMap, env, fs, path::{Path, PathBuf}, sync::OnceLock};
+
+static FLYRC: OnceLock<FlyRc> = OnceLock::new();
+
+const ENV_URL: &str = "CONCOURSE_URL";
+const ENV_TEAM: &str = "CONCOURSE_TEAM";
+const ENV_TOKEN: &str = "CONCOURSE_TOKEN";
+
+/// One entry under `targets:` in `.flyrc`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Target {
+    pub api: Option<String>,
+    pub team: Option<String>,
+}
+
+impl Target {
+    /// The target described by `CONCOURSE_URL` and `CONCOURSE_TEAM`, as set up
+    /// in containers that never write a `.flyrc`.
+    pub fn from_env() -> Self {
+        Target {
+            api: env::var(ENV_URL).ok().filter(|v| !v.is_empty()),
+            team: env::var(ENV_TEAM).ok().filter(|v| !v.is_empty()),
+        }
+    }
+
+    /// Whether the environment names a whole target: URL, team and `CONCOURSE_TOKEN`.
+    /// When it does it takes precedence over `.flyrc`.
+    pub fn env_is_complete() -> bool {
+        let target = Self::from_env();
+        target.api.is_some() && target.team.is_some() && env::var_os(ENV_TOKEN).is_some_and(|t| !t.is_empty())
+    }
+}
+
+/// The fly targets configured in `.flyrc`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FlyRc {
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(default)]
+    targets: BTreeMap<String, Target>,
+}
+
+impl FlyRc {
+    /// The `.flyrc` fly itself would read: `~/.flyrc`, then `$FLYRC`, then `./.flyrc`.
+    pub fn find() -> Option<PathBuf> {
+        [
+            dirs::home_dir().map(|p| p.join(".flyrc")),
+            env::var("FLYRC").ok().map(PathBuf::from),
+            Some(PathBuf::from(".flyrc")),
+        ].into_iter().flatten().find(|p| p.exists())
+    }
+
+    /// Finds and parses `.flyrc`.
+    pub fn load() -> Result<Self> {
+        let path = Self::find().ok_or_else(|| anyhow!("No .flyrc found; run 'fly login' first"))?;
+        Self::load_from(&path)
+    }
+
+    /// Parses `path`, pointing parse errors at `file:line:column` and treating
+    /// a `.flyrc` without targets as not logged in.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("{} is not readable: {}", path.display(), e))?;
+        if contents.trim().is_empty() {
+            bail!("{} is empty; run 'fly -t <target> login -c <url>' first", path.display());
+        }
+        let mut flyrc: FlyRc = serde_yaml::from_str(&contents).map_err(|e| {
+            let at = e.location().map(|l| format!(":{}:{}", l.line(), l.column())).unwrap_or_default();
+            anyhow!("{}{}: .flyrc is not valid YAML: {}", path.display(), at, e)
+        })?;
+        if flyrc.targets.is_empty() {
+            bail!("{} has no targets; run 'fly -t <target> login -c <url>' first", path.display());
+        }
+        flyrc.path = path.to_path_buf();
+        Ok(flyrc)
+    }
+
+    /// `.flyrc`, read on first use and shared for the rest of the run.
+    pub fn shared() -> Result<&'static Self> {
+        if let Some(flyrc) = FLYRC.get() {
+            return Ok(flyrc);
+        }
+        let flyrc = Self::load()?;
+        Ok(FLYRC.get_or_init(|| flyrc))
+    }
+
+    pub fn target(&self, name: &str) -> Option<&Target> {
+        self.targets.get(name)
+    }
+
+    pub fn target_names(&self) -> impl Iterator<Item = &str> {
+        self.targets.keys().map(String::as_str)
+    }
+
+    /// Every team named by a target, sorted and without repeats.
+    pub fn teams(&self) -> Vec<String> {
+        let mut teams: Vec<String> = self.targets.values().filter_map(|t| t.team.clone()).collect();
+        teams.sort();
+        teams.dedup();
+        teams
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `contents` written to a `.flyrc` in a scratch directory.
+    fn fixture(contents: &str) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".flyrc");
+        fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    const MULTI_TARGET: &str = r#"
+targets:
+  ci:
+    api: https://ci.example.com
+    team: main
+    token:
+      type: bearer
+      value: abc
+  prod:
+    api: https://prod.example.com
+    team: ops
+  staging:
+    api: https://staging.example.com
+    team: main
+  bare: {}
+"#;
+
+    #[test]
+    fn load_from_reads_every_target() {
+        let (_dir, path) = fixture(MULTI_TARGET);
+        let flyrc = FlyRc::load_from(&path).unwrap();
+        assert_eq!(flyrc.path, path);
+        assert_eq!(flyrc.target_names().collect::<Vec<_>>(), ["bare", "ci", "prod", "staging"]);
+        let prod = flyrc.target("prod").unwrap();
+        assert_eq!((prod.api.as_deref(), prod.team.as_deref()), (Some("https://prod.example.com"), Some("ops")));
+        assert_eq!(flyrc.target("bare").unwrap().api, None);
+        assert!(flyrc.target("missing").is_none());
+        assert_eq!(flyrc.teams(), ["main", "ops"]);
+    }
+
+    #[test]
+    fn load_from_rejects_empty_and_target_less_files() {
+        for contents in ["", "  \n\n"] {
+            let (_dir, path) = fixture(contents);
+            let err = FlyRc::load_from(&path).unwrap_err().to_string();
+            assert!(err.ends_with("is empty; run 'fly -t <target> login -c <url>' first"), "{}", err);
+        }
+        for contents in ["targets: {}\n", "---\nother: 1\n"] {
+            let (_dir, path) = fixture(contents);
+            let err = FlyRc::load_from(&path).unwrap_err().to_string();
+            assert!(err.contains("has no targets"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn load_from_points_parse_errors_at_their_location() {
+        let (_dir, path) = fixture("targets:\n  ci:\n    api: https://ci.example.com\n  team: [main\n");
+        let err = FlyRc::load_from(&path).unwrap_err().to_string();
+        let at = format!("{}:4:", path.display());
+        assert!(err.starts_with(&at), "{}", err);
+        assert!(err.contains(".flyrc is not valid YAML"), "{}", err);
+
+        let missing = path.with_file_name("missing");
+        assert!(FlyRc::load_from(&missing).unwrap_err().to_string().contains("is not readable"));
+    }
+}
@@ -0,0 +1,80 @@
+// src/git.rs
+use anyhow::{bail, Context, Result};
+use std::{path::{Path, PathBuf}, process::Command};
+use crate::process::RunExt;
+
+fn git(dir: &Path, args: &[&str]) -> Result<std::process::Output> {
+    Command::new("git")
+        .arg("-C").arg(dir)
+        .args(args)
+        .run_output()
+        .context("Failed to run git; is it installed?")
+}
+
+/// Top of the git checkout containing `dir`, canonicalized so paths built
+/// on it compare equal to other canonical paths.
+pub fn toplevel(dir: &Path) -> Result<PathBuf> {
+    let output = git(dir, &["rev-parse", "--show-toplevel"])?;
+    if !output.status.success() {
+        bail!("{} is not inside a git repository", dir.display());
+    }
+    let top = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    top.canonicalize()
+        .with_context(|| format!("Failed to resolve git checkout {}", top.display()))
+}
+
+/// Absolute paths of files that differ between `since` and the working tree.
+pub fn changed_since(dir: &Path, since: &str) -> Result<Vec<PathBuf>> {
+    let top = toplevel(dir)?;
+
+    let verify = git(&top, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", since)])?;
+    if !verify.status.success() {
+        bail!("'{}' is not a valid git ref in {}", since, top.display());
+    }
+
+    let output = git(&top, &["diff", "--name-only", since])?;
+    if !output.status.success() {
+        bail!("git diff against '{}' failed: {}", since, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| top.join(line))
+        .collect())
+}
+
+/// Whether git tracks `path`; false outside a checkout.
+pub fn is_tracked(path: &Path) -> Result<bool> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(false);
+    };
+    let output = Command::new("git")
+        .arg("-C").arg(dir)
+        .args(["ls-files", "--error-unmatch", "--"]).arg(name)
+        .run_output()
+        .context("Failed to run git; is it installed?")?;
+    Ok(output.status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn changed_paths_are_canonical_when_reached_through_a_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().canonicalize().unwrap().join("repo");
+        std::fs::create_dir_all(real.join("bosh/ci")).unwrap();
+        std::fs::write(real.join("bosh/ci/settings.yml"), "a: 1\n").unwrap();
+        let linked = dir.path().join("linked");
+        std::os::unix::fs::symlink(&real, &linked).unwrap();
+        for args in [&["init", "-q"][..], &["add", "."], &["-c", "user.name=gk", "-c", "user.email=gk@example.com", "commit", "-qm", "init"]] {
+            assert!(git(&real, args).unwrap().status.success(), "git {:?} failed", args);
+        }
+        std::fs::write(real.join("bosh/ci/settings.yml"), "a: 2\n").unwrap();
+
+        assert_eq!(changed_since(&linked, "HEAD").unwrap(), [real.join("bosh/ci/settings.yml")]);
+        assert!(changed_since(&linked, "no-such-ref").is_err());
+    }
+}
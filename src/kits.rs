@@ -0,0 +1,712 @@
+// src/kits.rs
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use regex::Regex;
+use log::{debug, warn};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+use crate::{config::{config, Config}, constants::{AVAILABLE_KITS, CI_TARGET}, spruce};
+
+const IGNORE_FILE: &str = ".gkmignore";
+const KIT_FILE: &str = "kit.yml";
+const MANIFEST_FILE: &str = "kits.yml";
+
+static KIT_ROOT: OnceLock<PathBuf> = OnceLock::new();
+static PIPELINE_PREFIX: OnceLock<String> = OnceLock::new();
+static SETTINGS_LOOKUP: OnceLock<SettingsLookup> = OnceLock::new();
+
+/// Points discovery at `path` instead of the working directory (`--kit-root`).
+pub fn set_kit_root(path: &Path) -> Result<()> {
+    if !path.exists() {
+        bail!("Kit root {} does not exist", path.display());
+    }
+    if !path.is_dir() {
+        bail!("Kit root {} is not a directory", path.display());
+    }
+    let path = path.canonicalize()
+        .with_context(|| format!("Failed to resolve kit root {}", path.display()))?;
+    let _ = KIT_ROOT.set(path);
+    Ok(())
+}
+
+/// Where kit and ci directory discovery starts: `--kit-root`, or the working directory.
+pub fn kit_root() -> Result<PathBuf> {
+    match KIT_ROOT.get() {
+        Some(root) => Ok(root.clone()),
+        None => env::current_dir().context("Failed to get current directory"),
+    }
+}
+
+/// The kit root the tests share, since `--kit-root` is set once per process: a
+/// `kits.yml` listing `bosh` checked out at `checkouts/bosh-genesis-kit` and
+/// `alpha`, whose pipeline the manifest names.
+#[cfg(test)]
+pub(crate) fn test_kit_root() -> &'static Path {
+    static ROOT: OnceLock<PathBuf> = OnceLock::new();
+    ROOT.get_or_init(|| {
+        let root = tempfile::tempdir().unwrap().keep();
+        for checkout in ["checkouts/bosh-genesis-kit", "alpha"] {
+            fs::create_dir_all(root.join(checkout).join("ci")).unwrap();
+        }
+        fs::write(root.join(MANIFEST_FILE), "kits:\n  - name: bosh\n    path: checkouts/bosh-genesis-kit\n  - name: alpha\n    pipeline: genesis-kit-alpha\n").unwrap();
+        set_kit_root(&root).unwrap();
+        kit_root().unwrap()
+    })
+}
+
+/// `--pipeline-prefix`: namespaces every pipeline name that comes from meta or
+/// the naming convention. Names given with `--pipeline` are used as they are.
+pub fn set_pipeline_prefix(prefix: &str) {
+    let _ = PIPELINE_PREFIX.set(prefix.to_string());
+}
+
+#[cfg(test)]
+thread_local! {
+    /// A `--pipeline-prefix` for the current test only; the real one is set once per process.
+    pub(crate) static TEST_PIPELINE_PREFIX: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// `name` with the `--pipeline-prefix` in front, if one was given.
+pub fn prefixed_pipeline_name(name: &str) -> String {
+    #[cfg(test)]
+    if let Some(prefix) = TEST_PIPELINE_PREFIX.with(|prefix| prefix.borrow().clone()) {
+        return format!("{}{}", prefix, name);
+    }
+    format!("{}{}", PIPELINE_PREFIX.get().map_or("", String::as_str), name)
+}
+
+/// The `meta` block of a kit's merged pipeline config.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PipelineMeta {
+    pub target: Option<String>,
+    pub url: Option<String>,
+    pub team: Option<String>,
+    pub pipeline: Option<String>,
+    pub name: Option<String>,
+    pub exposed: Option<bool>,
+}
+
+impl PipelineMeta {
+    /// The `meta` of the first document in a merged pipeline; a pipeline without one has empty meta.
+    pub fn from_merged(yaml: &str) -> Result<PipelineMeta> {
+        let documents = spruce::parse_documents(yaml).context("Merged pipeline is invalid YAML")?;
+        match documents.first().and_then(|pipeline| pipeline.get("meta")) {
+            Some(meta) => Self::from_value(meta),
+            None => {
+                warn!("Pipeline has no top-level meta; relying on flags and environment");
+                Ok(PipelineMeta::default())
+            }
+        }
+    }
+
+    /// A null `meta` is treated as empty; anything but a mapping is ignored with a warning.
+    pub fn from_value(meta: &serde_yaml::Value) -> Result<PipelineMeta> {
+        match meta {
+            serde_yaml::Value::Null => {
+                warn!("Pipeline meta is empty; relying on flags and environment");
+                Ok(PipelineMeta::default())
+            }
+            serde_yaml::Value::Mapping(_) => serde_yaml::from_value(meta.clone())
+                .context("Pipeline `meta` has unexpected field types"),
+            other => {
+                let kind = match other {
+                    serde_yaml::Value::Sequence(_) => "a list",
+                    _ => "a scalar",
+                };
+                warn!("Pipeline `meta` is {} rather than a mapping; ignoring it", kind);
+                Ok(PipelineMeta::default())
+            }
+        }
+    }
+}
+
+/// How the settings file merged over a kit's pipeline is picked.
+#[derive(Debug, Clone, Default)]
+pub struct SettingsLookup {
+    /// `--settings`, relative to the ci directory; wins over everything else
+    pub settings: Option<String>,
+    /// `--env`
+    pub env: Option<String>,
+    /// `--target`, else `CONCOURSE_TARGET`
+    pub target: Option<String>,
+}
+
+/// `--settings`, `--env` and `--target` for the kits the ci commands read meta from.
+pub fn set_settings_lookup(lookup: SettingsLookup) {
+    let _ = SETTINGS_LOOKUP.set(lookup);
+}
+
+impl SettingsLookup {
+    /// The settings files tried, in order: each of the env and target flat
+    /// (`settings-<name>.yml`) and then nested (`<settings_dir>/<name>.yml`),
+    /// env before target, then plain `settings.yml`.
+    pub fn candidates(&self, settings_dir: &str) -> Vec<String> {
+        let target = self.target.clone().or_else(|| env::var("CONCOURSE_TARGET").ok());
+        let mut candidates: Vec<String> = [self.env.clone(), target.map(|target| target.replace(['/', ' '], "-"))]
+            .into_iter()
+            .flatten()
+            .flat_map(|name| [format!("settings-{}.yml", name), format!("{}/{}.yml", settings_dir, name)])
+            .collect();
+        candidates.push(String::from("settings.yml"));
+        candidates
+    }
+
+    /// The settings file for the pipeline in `ci_dir`.
+    pub fn find(&self, ci_dir: &Path) -> Result<PathBuf> {
+        if let Some(settings) = &self.settings {
+            let path = ci_dir.join(settings);
+            if !path.exists() {
+                bail!("Settings file '{}' passed with --settings does not exist", settings);
+            }
+            return Ok(path);
+        }
+
+        let candidates = self.candidates(config().settings_dir());
+        debug!("Looking for settings in order: {}", candidates.join(", "));
+        match candidates.iter().map(|file| ci_dir.join(file)).find(|path| path.exists()) {
+            Some(path) => {
+                debug!("Using settings file {}", path.display());
+                Ok(path)
+            }
+            None => bail!("Missing local settings in {}. Checked:\n{}\nPass --settings to use another file",
+                ci_dir.display(),
+                candidates.iter().map(|file| format!("- {}", file)).collect::<Vec<_>>().join("\n")),
+        }
+    }
+}
+
+/// The parts of `kit.yml` gk reads.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct KitFile {
+    version: Option<String>,
+    template_version: Option<String>,
+}
+
+/// One kit listed in a `kits.yml` manifest.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestKit {
+    pub name: String,
+    /// The kit's checkout, holding its `ci/`, relative to the manifest; the
+    /// kit name when unset
+    pub path: Option<PathBuf>,
+    /// Pipeline name, used instead of merging the kit's meta
+    pub pipeline: Option<String>,
+    pub target: Option<String>,
+    pub team: Option<String>,
+}
+
+/// `kits.yml`: an explicit kit list that replaces directory discovery.
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    kits: Vec<ManifestKit>,
+}
+
+/// The manifest in `root` or, failing that, at the root of its git checkout,
+/// with the directory its paths are relative to.
+fn load_manifest(root: &Path) -> Result<Option<(Manifest, PathBuf)>> {
+    let Some(path) = [root.to_path_buf(), repo_root(root)]
+        .into_iter()
+        .map(|d| d.join(MANIFEST_FILE))
+        .find(|p| p.is_file())
+    else {
+        return Ok(None);
+    };
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let manifest: Manifest = serde_yaml::from_str(&contents)
+        .with_context(|| format!("Invalid kit manifest {}", path.display()))?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    Ok(Some((manifest, dir)))
+}
+
+impl ManifestKit {
+    /// The kit's checkout, given the manifest's directory.
+    fn dir(&self, manifest_dir: &Path) -> PathBuf {
+        manifest_dir.join(self.path.as_deref().unwrap_or(Path::new(&self.name)))
+    }
+}
+
+/// The entry of the kit root's manifest whose checkout holds `ci_dir`, if any.
+pub fn manifest_kit(ci_dir: &Path) -> Option<ManifestKit> {
+    let (manifest, dir) = load_manifest(&kit_root().ok()?)
+        .map_err(|e| warn!("{:#}", e))
+        .ok()??;
+    let ci_dir = ci_dir.canonicalize().ok()?;
+    manifest.kits.into_iter()
+        .find(|entry| entry.dir(&dir).join("ci").canonicalize().is_ok_and(|path| path == ci_dir))
+}
+
+/// A kit and the metadata read from its checkout.
+#[derive(Debug, Clone)]
+pub struct Kit {
+    pub name: String,
+    /// The kit's `ci/` directory
+    pub path: PathBuf,
+    /// `version` from `kit.yml`
+    pub version: Option<Version>,
+    /// `template_version` from `kit.yml`
+    pub template_version: Option<Version>,
+    /// The kit's `kits.yml` entry, when it was listed in one
+    pub manifest: Option<ManifestKit>,
+    /// Filled by the first `meta()` call and shared between clones
+    meta: Arc<OnceLock<PipelineMeta>>,
+}
+
+impl Kit {
+    /// Reads the kit checked out in `dir`, which must hold a `ci/` directory.
+    /// `kit.yml` is optional; versions that aren't semver are warned about and left unset.
+    pub fn load(dir: &Path) -> Result<Kit> {
+        let name = dir.file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .with_context(|| format!("{} has no kit name", dir.display()))?;
+        let path = dir.join("ci");
+        if !path.is_dir() {
+            bail!("{} has no ci/ directory", dir.display());
+        }
+
+        let kit_file = dir.join(KIT_FILE);
+        let file: KitFile = if kit_file.is_file() {
+            let contents = fs::read_to_string(&kit_file)
+                .with_context(|| format!("Failed to read {}", kit_file.display()))?;
+            serde_yaml::from_str(&contents)
+                .with_context(|| format!("Invalid YAML in {}", kit_file.display()))?
+        } else {
+            KitFile::default()
+        };
+
+        Ok(Kit {
+            version: parse_version(&kit_file, "version", file.version.as_deref()),
+            template_version: parse_version(&kit_file, "template_version", file.template_version.as_deref()),
+            ..Kit::named(name, path)
+        })
+    }
+
+    /// A kit known only by name and ci directory.
+    fn named(name: String, path: PathBuf) -> Kit {
+        Kit { name, path, version: None, template_version: None, manifest: None, meta: Arc::default() }
+    }
+
+    /// The kit's pipeline meta, merged with spruce on first use.
+    pub fn meta(&self) -> Result<&PipelineMeta> {
+        if let Some(meta) = self.meta.get() {
+            return Ok(meta);
+        }
+        let meta = load_pipeline_meta(&self.path)?;
+        Ok(self.meta.get_or_init(|| meta))
+    }
+
+    /// The Concourse pipeline name from the kit's manifest entry or meta, or
+    /// the configured convention.
+    pub fn pipeline_name(&self) -> Result<String> {
+        if let Some(pipeline) = self.manifest.as_ref().and_then(|m| m.pipeline.as_deref()) {
+            return Ok(prefixed_pipeline_name(pipeline));
+        }
+        let meta = self.meta()?;
+        let name = meta.pipeline.clone()
+            .or_else(|| meta.name.clone())
+            .unwrap_or_else(|| config().pipeline_name(&self.name));
+        Ok(prefixed_pipeline_name(&name))
+    }
+}
+
+/// The fly target and team for `kit`: its `kits:` entry in the config, then
+/// the `--target`/`--team` flags, then its `kits.yml` entry, then its pipeline meta, then the CI target
+/// and the target's own team. Meta is only merged when nothing earlier decides.
+pub fn resolve_kit_target(kit: &Kit, target: Option<&str>, team: Option<&str>) -> (String, Option<String>) {
+    resolve_kit_target_with(config(), kit, target, team, || kit.meta().ok())
+}
+
+/// [`resolve_kit_target`] against `settings` with the meta lookup supplied, so
+/// `--no-spruce` can skip it.
+pub(crate) fn resolve_kit_target_with<'a>(
+    settings: &Config,
+    kit: &Kit,
+    target: Option<&str>,
+    team: Option<&str>,
+    meta: impl Fn() -> Option<&'a PipelineMeta>,
+) -> (String, Option<String>) {
+    let configured = settings.kit(&kit.name).cloned().unwrap_or_default();
+    let manifest = kit.manifest.as_ref();
+    let target = configured.target
+        .or_else(|| target.map(String::from))
+        .or_else(|| manifest.and_then(|m| m.target.clone()))
+        .or_else(|| meta().and_then(|m| m.target.clone()))
+        .unwrap_or_else(|| CI_TARGET.to_string());
+    let team = configured.team
+        .or_else(|| team.map(String::from))
+        .or_else(|| manifest.and_then(|m| m.team.clone()))
+        .or_else(|| meta().and_then(|m| m.team.clone()));
+    (target, team)
+}
+
+impl fmt::Display for Kit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+fn parse_version(file: &Path, field: &str, version: Option<&str>) -> Option<Version> {
+    let version = version?;
+    Version::parse(version.trim_start_matches('v'))
+        .map_err(|e| warn!("Ignoring {} '{}' in {}: {}", field, version, file.display(), e))
+        .ok()
+}
+
+/// Merges the pipeline config in `ci_dir` with spruce and extracts its `meta` block.
+fn load_pipeline_meta(ci_dir: &Path) -> Result<PipelineMeta> {
+    let settings_file = SETTINGS_LOOKUP.get().cloned().unwrap_or_default().find(ci_dir)?;
+
+    let base_yml = ci_dir.join("pipeline").join("base.yml");
+    if !base_yml.exists() {
+        bail!("No pipeline/base.yml found");
+    }
+
+    let merged_config = spruce::merge(&[base_yml, settings_file], &spruce::Vault::default(), &[])
+        .context("Failed to merge pipeline config")?;
+
+    if !merged_config.status.success() {
+        bail!("Failed to merge pipeline config");
+    }
+    PipelineMeta::from_merged(&String::from_utf8(merged_config.stdout)?)
+}
+
+/// Where a built-in kit without its own checkout keeps its ci directory:
+/// `<kit>/ci`, `./ci` or `../ci` under the kit root, whichever exists first.
+fn fallback_ci_dir(root: &Path, kit: &str) -> PathBuf {
+    let candidates = [
+        Some(root.join(kit).join("ci")),
+        Some(root.join("ci")),
+        root.parent().map(|p| p.join("ci")),
+    ];
+    candidates.iter()
+        .flatten()
+        .find(|path| path.exists())
+        .cloned()
+        .unwrap_or_else(|| root.join(kit).join("ci"))
+}
+
+/// The nearest enclosing git checkout, or `dir` itself outside of one.
+fn repo_root(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|d| d.join(".git").exists())
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// Loads `.gkmignore` (gitignore-style globs) from `dir` or the repo root.
+fn load_ignore(dir: &Path) -> Option<Gitignore> {
+    let path = [dir.to_path_buf(), repo_root(dir)]
+        .into_iter()
+        .map(|d| d.join(IGNORE_FILE))
+        .find(|p| p.is_file())?;
+
+    let mut builder = GitignoreBuilder::new(path.parent()?);
+    if let Some(e) = builder.add(&path) {
+        warn!("Ignoring malformed {}: {}", path.display(), e);
+        return None;
+    }
+    builder.build().map_err(|e| warn!("Ignoring malformed {}: {}", path.display(), e)).ok()
+}
+
+/// Kits under the kit root: every immediate subdirectory with a `ci/`
+/// folder, or the built-in kit list when there are none. Kits matched by
+/// `.gkmignore` are left out, as are kits whose files can't be read.
+///
+/// A `kits.yml` manifest in the kit root (or its repo root) replaces all of
+/// that with exactly the kits it lists, in its order.
+pub fn discover_kits() -> Result<Vec<Kit>> {
+    discover_kits_in(&kit_root()?)
+}
+
+pub fn discover_kits_in(root: &Path) -> Result<Vec<Kit>> {
+    if let Some((manifest, dir)) = load_manifest(root)? {
+        return Ok(manifest.kits.into_iter()
+            .filter_map(|entry| {
+                let kit_dir = entry.dir(&dir);
+                Kit::load(&kit_dir)
+                    .map(|kit| Kit { name: entry.name.clone(), manifest: Some(entry), ..kit })
+                    .map_err(|e| warn!("Skipping {}: {:#}", kit_dir.display(), e))
+                    .ok()
+            })
+            .collect());
+    }
+
+    let mut dirs: Vec<PathBuf> = fs::read_dir(root)
+        .with_context(|| format!("Failed to read {}", root.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.join("ci").is_dir())
+        .collect();
+    dirs.sort();
+
+    let mut kits: Vec<Kit> = dirs.iter()
+        .filter_map(|dir| Kit::load(dir)
+            .map_err(|e| warn!("Skipping {}: {:#}", dir.display(), e))
+            .ok())
+        .collect();
+    if dirs.is_empty() {
+        kits = AVAILABLE_KITS.iter()
+            .map(|kit| Kit::named(kit.to_string(), fallback_ci_dir(root, kit)))
+            .collect();
+    }
+
+    if let Some(ignore) = load_ignore(root) {
+        kits.retain(|kit| !ignore.matched(root.join(&kit.name), true).is_ignore());
+    }
+    Ok(kits)
+}
+
+/// The kit closest to a mistyped name, if any is close enough to be a likely typo.
+fn closest_kit<'a>(kit: &str, kits: &'a [Kit]) -> Option<&'a str> {
+    let max_distance = (kit.chars().count() / 3).max(2);
+    kits.iter()
+        .map(|candidate| (strsim::levenshtein(kit, &candidate.name), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.name.as_str())
+}
+
+/// Kit names, for prompts and messages.
+pub fn kit_names(kits: &[Kit]) -> Vec<String> {
+    kits.iter().map(|kit| kit.name.clone()).collect()
+}
+
+/// Checks a kit named on the command line against `kits`, suggesting the
+/// nearest match when it isn't one of them.
+pub fn resolve_kit(kit: &str, kits: &[Kit]) -> Result<Kit> {
+    if let Some(found) = kits.iter().find(|k| k.name == kit) {
+        return Ok(found.clone());
+    }
+    match closest_kit(kit, kits) {
+        Some(suggestion) => bail!("Unknown kit '{}'; did you mean {}?", kit, suggestion),
+        None => bail!("Unknown kit '{}'. Available kits: {}", kit, kit_names(kits).join(", ")),
+    }
+}
+
+/// Narrows `kits` to names matching `--kit-filter` (an unanchored regex), if one was given.
+pub fn apply_kit_filter(kits: Vec<Kit>, filter: Option<&Regex>) -> Result<Vec<Kit>> {
+    let Some(filter) = filter else {
+        return Ok(kits);
+    };
+    let matched: Vec<Kit> = kits.iter().filter(|kit| filter.is_match(&kit.name)).cloned().collect();
+    if matched.is_empty() {
+        bail!("No kits match --kit-filter '{}' (kits: {})", filter, kit_names(&kits).join(", "));
+    }
+    Ok(matched)
+}
+
+/// Drops the kits named by `--exclude-kit`, warning about names that aren't among `kits`.
+pub fn exclude_kits(kits: Vec<Kit>, excluded: &[String]) -> Result<Vec<Kit>> {
+    if excluded.is_empty() {
+        return Ok(kits);
+    }
+    for name in excluded {
+        if !kits.iter().any(|kit| kit.name == *name) {
+            warn!("--exclude-kit '{}' doesn't match any kit", name);
+        }
+    }
+    let remaining: Vec<Kit> = kits.into_iter().filter(|kit| !excluded.contains(&kit.name)).collect();
+    if remaining.is_empty() {
+        bail!("Every kit was excluded by --exclude-kit");
+    }
+    Ok(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pipeline_meta_tolerates_missing_null_and_odd_meta() {
+        for yaml in ["jobs: []\n", "meta:\njobs: []\n", "meta: ~\n", "meta: [a, b]\n", "meta: nope\n", "meta: {}\n"] {
+            assert_eq!(PipelineMeta::from_merged(yaml).unwrap(), PipelineMeta::default(), "{:?}", yaml);
+        }
+        let meta = PipelineMeta::from_merged("meta:\n  target: ci\n  name: bosh\n  extra: kept out\n---\nmeta:\n  target: other\n").unwrap();
+        assert_eq!((meta.target.as_deref(), meta.name.as_deref()), (Some("ci"), Some("bosh")));
+        assert!(PipelineMeta::from_merged("meta:\n  exposed: [yes]\n").is_err());
+    }
+
+    #[test]
+    fn kit_load_reads_versions_from_kit_yml() {
+        let root = tempfile::tempdir().unwrap();
+        let fixture = |name: &str, kit_yml: Option<&str>| {
+            let dir = root.path().join(name);
+            fs::create_dir_all(dir.join("ci")).unwrap();
+            if let Some(contents) = kit_yml {
+                fs::write(dir.join(KIT_FILE), contents).unwrap();
+            }
+            Kit::load(&dir)
+        };
+        let versions = |kit: &Kit| (kit.version.as_ref().map(Version::to_string), kit.template_version.as_ref().map(Version::to_string));
+
+        let kit = fixture("bosh", Some("name: bosh\nversion: 2.1.0\ntemplate_version: v2.3.0-rc.1\n")).unwrap();
+        assert_eq!((kit.name.as_str(), kit.path.as_path()), ("bosh", root.path().join("bosh").join("ci").as_path()));
+        assert_eq!(versions(&kit), (Some(String::from("2.1.0")), Some(String::from("2.3.0-rc.1"))));
+        let kit = fixture("vault", Some("version: 1.4\ntemplate_version: latest\n")).unwrap();
+        assert_eq!(versions(&kit), (None, None));
+        let kit = fixture("shield", Some("version: 0.9.2\n")).unwrap();
+        assert_eq!(versions(&kit), (Some(String::from("0.9.2")), None));
+        let kit = fixture("concourse", None).unwrap();
+        assert_eq!(versions(&kit), (None, None));
+
+        let err = fixture("broken", Some("version: [unclosed\n")).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid YAML in"), "{:#}", err);
+        fs::create_dir_all(root.path().join("no-ci")).unwrap();
+        assert!(Kit::load(&root.path().join("no-ci")).unwrap_err().to_string().ends_with("has no ci/ directory"));
+    }
+
+    #[test]
+    fn kit_target_precedence_is_config_flag_meta_default() {
+        let settings: Config = serde_yaml::from_str("kits:\n  bosh: {target: pinned, team: ops}\n  vault: {team: sec}\n").unwrap();
+        let meta = PipelineMeta { target: Some(String::from("from-meta")), team: Some(String::from("meta-team")), ..Default::default() };
+        let cases = [
+            ("bosh", Some("flagged"), true, ("pinned", Some("ops"))),
+            ("vault", Some("flagged"), true, ("flagged", Some("sec"))),
+            ("vault", None, true, ("from-meta", Some("sec"))),
+            ("shield", Some("flagged"), true, ("flagged", Some("meta-team"))),
+            ("shield", None, true, ("from-meta", Some("meta-team"))),
+            ("shield", None, false, (CI_TARGET, None)),
+        ];
+        for (name, flag, has_meta, (target, team)) in cases {
+            let kit = Kit::named(name.to_string(), PathBuf::from(name).join("ci"));
+            let resolved = resolve_kit_target_with(&settings, &kit, flag, None, || has_meta.then_some(&meta));
+            assert_eq!((resolved.0.as_str(), resolved.1.as_deref()), (target, team), "{} {:?}", name, flag);
+        }
+
+        let mut kit = Kit::named(String::from("shield"), PathBuf::from("shield/ci"));
+        kit.manifest = Some(serde_yaml::from_str("{name: shield, target: listed, team: listed-team}").unwrap());
+        let resolved = resolve_kit_target_with(&settings, &kit, None, Some("flag-team"), || Some(&meta));
+        assert_eq!((resolved.0.as_str(), resolved.1.as_deref()), ("listed", Some("flag-team")));
+    }
+
+    fn named_kits(names: &[&str]) -> Vec<Kit> {
+        names.iter().map(|name| Kit::named(name.to_string(), PathBuf::from(name).join("ci"))).collect()
+    }
+
+    #[test]
+    fn closest_kit_suggests_only_likely_typos() {
+        let kits = named_kits(&["bosh", "concourse", "vault", "cf-app-autoscaler"]);
+        assert_eq!(closest_kit("bsoh", &kits), Some("bosh"));
+        assert_eq!(closest_kit("concorse", &kits), Some("concourse"));
+        assert_eq!(closest_kit("cf-app-autoscalr", &kits), Some("cf-app-autoscaler"));
+        assert_eq!(closest_kit("kubernetes", &kits), None);
+        assert_eq!(closest_kit("zzzzzzzzzzzz", &kits), None);
+    }
+
+    #[test]
+    fn resolve_kit_matches_exactly_or_suggests() {
+        let kits = named_kits(&["bosh", "vault"]);
+        assert_eq!(resolve_kit("vault", &kits).unwrap().name, "vault");
+        assert_eq!(resolve_kit("vualt", &kits).unwrap_err().to_string(), "Unknown kit 'vualt'; did you mean vault?");
+        assert_eq!(resolve_kit("postgres-operator", &kits).unwrap_err().to_string(),
+            "Unknown kit 'postgres-operator'. Available kits: bosh, vault");
+    }
+
+    #[test]
+    fn gkmignore_drops_matching_kit_dirs() {
+        let root = tempfile::tempdir().unwrap();
+        for kit in ["bosh", "vault", "deprecated", "wip-postgres", "wip-redis"] {
+            fs::create_dir_all(root.path().join(kit).join("ci")).unwrap();
+        }
+        fs::write(root.path().join(IGNORE_FILE), "# retired kits\ndeprecated\nwip-*/\n").unwrap();
+
+        let kits = discover_kits_in(root.path()).unwrap();
+        assert_eq!(kit_names(&kits), ["bosh", "vault"]);
+    }
+
+    #[test]
+    fn manifest_lists_kits_at_their_own_paths() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["checkouts/bosh-genesis-kit/ci", "vault/ci", "unlisted/ci"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        fs::write(root.path().join(MANIFEST_FILE), r#"
+kits:
+  - name: bosh
+    path: checkouts/bosh-genesis-kit
+    pipeline: bosh-ci
+    target: prod
+    team: ops
+  - name: vault
+  - name: missing
+"#).unwrap();
+
+        let kits = discover_kits_in(root.path()).unwrap();
+        assert_eq!(kit_names(&kits), ["bosh", "vault"]);
+        assert_eq!(kits[0].path, root.path().join("checkouts/bosh-genesis-kit/ci"));
+        assert_eq!(kits[0].pipeline_name().unwrap(), "bosh-ci");
+        let manifest = kits[0].manifest.as_ref().unwrap();
+        assert_eq!((manifest.target.as_deref(), manifest.team.as_deref()), (Some("prod"), Some("ops")));
+        assert_eq!(kits[1].path, root.path().join("vault/ci"));
+    }
+
+    #[test]
+    fn invalid_manifest_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(MANIFEST_FILE), "kits: nope\n").unwrap();
+        let err = discover_kits_in(root.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid kit manifest"));
+    }
+
+    #[test]
+    fn settings_precedence_is_flag_env_target_default() {
+        let ci_dir = tempfile::tempdir().unwrap();
+        let lookup = SettingsLookup { settings: None, env: Some(String::from("prod")), target: Some(String::from("ci/eu west")) };
+        assert_eq!(lookup.candidates("settings"), [
+            "settings-prod.yml", "settings/prod.yml",
+            "settings-ci-eu-west.yml", "settings/ci-eu-west.yml",
+            "settings.yml",
+        ]);
+
+        let err = lookup.find(ci_dir.path()).unwrap_err().to_string();
+        assert!(err.contains("- settings-prod.yml") && err.contains("- settings.yml"), "{}", err);
+        let mut expected = Vec::new();
+        for file in ["settings.yml", "settings-ci-eu-west.yml", "settings-prod.yml"] {
+            fs::write(ci_dir.path().join(file), "").unwrap();
+            expected.push(file);
+        }
+        for file in expected.iter().rev() {
+            assert_eq!(lookup.find(ci_dir.path()).unwrap(), ci_dir.path().join(file));
+            fs::remove_file(ci_dir.path().join(file)).unwrap();
+        }
+    }
+
+    #[test]
+    fn nested_settings_are_found_under_the_settings_dir() {
+        let ci_dir = tempfile::tempdir().unwrap();
+        let settings_dir = config().settings_dir();
+        let lookup = SettingsLookup { settings: None, env: Some(String::from("lab")), target: Some(String::from("ci")) };
+        let err = lookup.find(ci_dir.path()).unwrap_err().to_string();
+        for nested in [format!("- {}/lab.yml", settings_dir), format!("- {}/ci.yml", settings_dir)] {
+            assert!(err.contains(&nested), "{} not listed in\n{}", nested, err);
+        }
+
+        let nested = ci_dir.path().join(settings_dir);
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join("ci.yml"), "").unwrap();
+        assert_eq!(lookup.find(ci_dir.path()).unwrap(), nested.join("ci.yml"));
+        fs::write(nested.join("lab.yml"), "").unwrap();
+        assert_eq!(lookup.find(ci_dir.path()).unwrap(), nested.join("lab.yml"));
+
+        let configured: crate::config::Config = serde_yaml::from_str("settings_dir: environments\n").unwrap();
+        assert_eq!(lookup.candidates(configured.settings_dir())[1], "environments/lab.yml");
+    }
+
+    #[test]
+    fn settings_flag_is_read_from_the_ci_dir() {
+        let ci_dir = tempfile::tempdir().unwrap();
+        fs::write(ci_dir.path().join("settings.yml"), "").unwrap();
+        fs::write(ci_dir.path().join("mine.yml"), "").unwrap();
+        let lookup = SettingsLookup { settings: Some(String::from("mine.yml")), env: Some(String::from("prod")), target: None };
+        assert_eq!(lookup.find(ci_dir.path()).unwrap(), ci_dir.path().join("mine.yml"));
+
+        let missing = SettingsLookup { settings: Some(String::from("gone.yml")), ..Default::default() };
+        assert!(missing.find(ci_dir.path()).unwrap_err().to_string().contains("--settings"));
+    }
+}
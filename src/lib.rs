@@ -0,0 +1,29 @@
+// src/lib.rs
+//! Genesis Kit Manager as a library. The `gk` binary is a thin clap layer over
+//! this crate; the non-interactive entry points are:
+//!
+//! - [`commands::repipe::RepipeCommand`]: merge and set a kit's pipeline, returning a
+//!   [`commands::repipe::RepipeResult`]
+//! - [`commands::ci::collect_statuses`]: CI status for a list of kits
+//! - [`commands::template::update_template_version`]: check a template version bump
+//!
+//! fly is reached through the [`fly::FlyClient`] trait, so callers can supply
+//! their own client.
+pub mod ui;
+pub mod commands;
+pub mod types;
+pub mod constants;
+pub mod process;
+pub mod cache;
+pub mod config;
+pub mod fly;
+pub mod flyrc;
+pub mod kits;
+pub mod git;
+pub mod spruce;
+
+pub use ui::GenesisKitUI;
+
+/// Serializes tests that set environment variables, which are process-wide.
+#[cfg(test)]
+pub(crate) static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
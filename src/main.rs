@@ -1,33 +1,775 @@
 // src/main.rs
-use clap::Command;
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use anyhow::Result;
-mod ui;
-mod commands;
-mod types;
-mod constants;
-
-use ui::GenesisKitUI;
+use std::{io::Write, path::PathBuf};
+use gkm::{
+    GenesisKitUI,
+    commands::{ci::{CiError, CiOptions}, init::InitOptions, repipe::{self, RepipeOptions}},
+    constants::{CI_TARGET, ENVIRONMENTS},
+    config,
+    ui::{self, format::OutputFormat, styles::Theme},
+    fly::ProcessFlyClient,
+    kits,
+    process,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let ui = GenesisKitUI::new();
-    ui.display_welcome()?;
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            // Drop doesn't run when we're interrupted mid-fly call
+            repipe::cleanup_interrupted();
+            let _ = console::Term::stdout().show_cursor();
+            std::process::exit(130);
+        }
+    });
 
+    match run().await {
+        Err(e) => match e.downcast_ref::<CiError>() {
+            Some(ci_error) => {
+                eprintln!("{}", e);
+                std::process::exit(ci_error.exit_code());
+            }
+            None => Err(e),
+        },
+        ok => ok,
+    }
+}
+
+async fn run() -> Result<()> {
     let cli = Command::new("gk")
         .about("Genesis Kit Management Tool")
-        .subcommand(Command::new("repipe").about("Update Concourse pipelines"))
-        .subcommand(Command::new("template").about("Manage kit template versions"))
-        .subcommand(Command::new("ci").about("Manage CI configuration"))
+        .arg(Arg::new("verbose")
+            .short('V')
+            .long("verbose")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Log every fly/spruce invocation (secrets redacted)"))
+        .arg(Arg::new("log-format")
+            .long("log-format")
+            .value_name("FORMAT")
+            .value_parser(["human", "json"])
+            .global(true)
+            .help("Log line format; defaults to $GKM_LOG_FORMAT or human"))
+        .arg(Arg::new("kit-root")
+            .long("kit-root")
+            .value_name("PATH")
+            .global(true)
+            .value_parser(clap::value_parser!(PathBuf))
+            .help("Kit repository to work in instead of the current directory"))
+        .arg(Arg::new("pipeline-prefix")
+            .long("pipeline-prefix")
+            .value_name("PREFIX")
+            .global(true)
+            .help("Prepend PREFIX to pipeline names from meta or the naming convention (not to --pipeline)"))
+        .arg(Arg::new("no-banner")
+            .long("no-banner")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Skip the welcome banner (or set GKM_NO_BANNER)"))
+        .arg(Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Only print warnings, errors and final results"))
+        .arg(Arg::new("no-color")
+            .long("no-color")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Plain output: no colors in tables, diffs or messages"))
+        .arg(Arg::new("skip-checks")
+            .long("skip-checks")
+            .visible_alias("assume-fly-logged-in")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Skip the fly/spruce/jq preflight probes; problems then surface from the commands themselves"))
+        .arg(Arg::new("clean-env")
+            .long("clean-env")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Run fly/spruce with only PATH, HOME, CONCOURSE_* and VAULT_* from the environment"))
+        .arg(Arg::new("pass-env")
+            .long("pass-env")
+            .value_name("VAR")
+            .global(true)
+            .action(ArgAction::Append)
+            .requires("clean-env")
+            .help("Also pass VAR through under --clean-env; repeatable"))
+        .arg(Arg::new("interactive")
+            .long("interactive")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .overrides_with("no-interactive")
+            .help("Prompt even when no terminal is detected"))
+        .arg(Arg::new("no-interactive")
+            .long("no-interactive")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .overrides_with("interactive")
+            .help("Never prompt; fail when a value a prompt would ask for is missing"))
+        .arg(Arg::new("no-memory")
+            .long("no-memory")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Don't read or save the last-used kit and target"))
+        .subcommand(Command::new("repipe")
+            .about("Update Concourse pipelines")
+            .arg(fly_args_arg("set-pipeline"))
+            .arg(Arg::new("validate")
+                .short('v')
+                .long("validate")
+                .action(ArgAction::Count)
+                .help("Validate the pipeline instead of setting it (twice for --strict)"))
+            .arg(Arg::new("validate-only")
+                .long("validate-only")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["dry-run", "print", "debug"])
+                .help("Validate the merged pipeline offline, skipping the .flyrc target checks"))
+            .arg(Arg::new("dry-run")
+                .short('n')
+                .long("dry-run")
+                .action(ArgAction::Count)
+                .help("Print the fly commands that would set the pipeline instead of running them (--print shows the merged YAML)"))
+            .arg(Arg::new("print")
+                .long("print")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["validate", "dry-run", "debug"])
+                .help("Write the merged pipeline YAML to stdout without creating .deploy.yml or calling fly"))
+            .arg(Arg::new("all")
+                .long("all")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["print", "debug", "pipeline"])
+                .help("Repipe every kit under the kit root and summarise the results"))
+            .arg(Arg::new("env")
+                .long("env")
+                .value_name("ENV")
+                .value_parser(ENVIRONMENTS.to_vec())
+                .help("Use settings-<ENV>.yml or settings/<ENV>.yml when it exists"))
+            .arg(Arg::new("settings")
+                .long("settings")
+                .value_name("FILE")
+                .help("Settings file to merge, overriding --env and target-based lookup"))
+            .arg(Arg::new("spruce-arg")
+                .long("spruce-arg")
+                .value_name("FLAG")
+                .action(ArgAction::Append)
+                .allow_hyphen_values(true)
+                .help("Extra flag for spruce merge, e.g. --spruce-arg=--go-patch (repeatable). \
+                       Passed through unchecked, so it can produce a config fly rejects"))
+            .arg(Arg::new("vault-addr")
+                .long("vault-addr")
+                .value_name("URL")
+                .help("Vault for (( vault )) lookups during the merge (default $VAULT_ADDR)"))
+            .arg(Arg::new("vault-token")
+                .long("vault-token")
+                .value_name("TOKEN")
+                .help("Vault token for (( vault )) lookups (default $VAULT_TOKEN)"))
+            .arg(Arg::new("strict")
+                .long("strict")
+                .action(ArgAction::SetTrue)
+                .help("Fail when the merged pipeline is missing jobs or resources"))
+            .arg(Arg::new("strict-target")
+                .long("strict-target")
+                .action(ArgAction::SetTrue)
+                .help("Fail when meta.url or meta.team disagree with the .flyrc target"))
+            .arg(Arg::new("prune")
+                .long("prune")
+                .value_name("KEY")
+                .action(ArgAction::Append)
+                .help("Prune KEY from the merged pipeline (repeatable; default: the config's prune list, or meta)"))
+            .arg(Arg::new("redact")
+                .long("redact")
+                .action(ArgAction::SetTrue)
+                .help("Mask values under password/token/secret/key-like keys in printed, diffed and saved YAML \
+                       (the config given to fly is untouched)"))
+            .arg(Arg::new("dump-meta")
+                .long("dump-meta")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["print", "validate-only"])
+                .help("Print the resolved pipeline meta as JSON, with where each field came from"))
+            .arg(Arg::new("dump-meta-only")
+                .long("dump-meta-only")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["print", "validate-only"])
+                .help("Like --dump-meta, then stop before checking the target or calling fly"))
+            .arg(Arg::new("instance-var")
+                .long("instance-var")
+                .value_name("NAME=VALUE")
+                .action(ArgAction::Append)
+                .value_parser(name_value_pair)
+                .help("Set an instanced pipeline with this instance var (repeatable)"))
+            .arg(Arg::new("check-creds")
+                .long("check-creds")
+                .action(ArgAction::SetTrue)
+                .help("Have fly set-pipeline check every ((var)) resolves, if the target supports it"))
+            .arg(Arg::new("skip-flyrc-check")
+                .long("skip-flyrc-check")
+                .action(ArgAction::SetTrue)
+                .help("Don't require the target in .flyrc; check meta against CONCOURSE_URL/CONCOURSE_TEAM instead"))
+            .arg(Arg::new("deploy-file")
+                .long("deploy-file")
+                .value_name("NAME")
+                .help("Name of the merged config written in the ci directory (default .deploy.yml)"))
+            .arg(kit_filter_arg().requires("all"))
+            .arg(exclude_kit_arg().requires("all"))
+            .arg(Arg::new("since")
+                .long("since")
+                .value_name("REF")
+                .requires("all")
+                .help("With --all, only repipe kits with changes since this git ref"))
+            .arg(Arg::new("native-jobs")
+                .long("native-jobs")
+                .action(ArgAction::SetTrue)
+                .help("Generate test/upstream jobs in gk instead of running the kit's build-*-jobs scripts"))
+            .arg(Arg::new("parallel-scripts")
+                .long("parallel-scripts")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["native-jobs", "skip-scripts"])
+                .help("Run the kit's job-builder scripts at the same time, failing if any fails"))
+            .arg(Arg::new("skip-scripts")
+                .long("skip-scripts")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("native-jobs")
+                .help("Don't run the kit's job-builder scripts; use the job files already generated"))
+            .arg(Arg::new("no-hooks")
+                .long("no-hooks")
+                .action(ArgAction::SetTrue)
+                .help("Don't run ci/hooks/pre-repipe before or ci/hooks/post-repipe after the pipeline is set"))
+            .arg(Arg::new("pause")
+                .long("pause")
+                .action(ArgAction::SetTrue)
+                .help("Leave the pipeline paused after setting it"))
+            .arg(Arg::new("expose")
+                .long("expose")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-expose")
+                .help("Expose the pipeline (overrides meta.exposed)"))
+            .arg(Arg::new("no-expose")
+                .long("no-expose")
+                .action(ArgAction::SetTrue)
+                .overrides_with("expose")
+                .help("Hide the pipeline (overrides meta.exposed)"))
+            .arg(Arg::new("open")
+                .short('o')
+                .long("open")
+                .action(ArgAction::Count)
+                .help("Open the pipeline in a browser afterwards"))
+            .arg(Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .action(ArgAction::SetTrue)
+                .help("Don't prompt before setting the pipeline"))
+            .arg(Arg::new("fly")
+                .long("fly")
+                .value_name("PATH")
+                .help("Path to the fly binary"))
+            .arg(Arg::new("target")
+                .short('t')
+                .long("target")
+                .value_name("TARGET")
+                .help("Concourse target (overrides meta.target and CONCOURSE_TARGET)"))
+            .arg(Arg::new("pipeline")
+                .short('p')
+                .long("pipeline")
+                .value_name("NAME")
+                .help("Set the config under this pipeline name instead of meta.pipeline/meta.name"))
+            .arg(Arg::new("debug")
+                .short('d')
+                .long("debug")
+                .action(ArgAction::SetTrue)
+                .help("Write the merged config to repipe-debug.yml and stop"))
+            .arg(Arg::new("output-dir")
+                .long("output-dir")
+                .value_name("DIR")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all(["print", "validate-only"])
+                .help("Also save each merged pipeline as DIR/<pipeline>.yml (created if needed); kept after the run"))
+            .arg(Arg::new("no-cleanup")
+                .long("no-cleanup")
+                .action(ArgAction::SetTrue)
+                .help("Keep the deploy file and generated pipeline/tests, pipeline/upstream jobs; \
+                       later runs may then pick up stale job files")))
+        .subcommand(Command::new("template")
+            .about("Manage kit template versions")
+            .subcommand(Command::new("diff")
+                .about("Preview the file changes a template version bump would make")
+                .arg(kit_arg())
+                .arg(Arg::new("version")
+                    .long("version")
+                    .value_name("VERSION")
+                    .required(true)
+                    .help("Template version to preview"))))
+        .subcommand(Command::new("init")
+            .about("Scaffold a minimal ci/ directory for a new kit")
+            .arg(Arg::new("kit")
+                .long("kit")
+                .value_name("KIT")
+                .help("Kit name (prompts when omitted, defaulting to the directory name)"))
+            .arg(Arg::new("target")
+                .long("target")
+                .value_name("TARGET")
+                .help("Concourse target written to meta.target"))
+            .arg(Arg::new("team")
+                .long("team")
+                .value_name("TEAM")
+                .help("Concourse team written to meta.team"))
+            .arg(Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Overwrite the generated files in an existing ci/")))
+        .subcommand(Command::new("doctor")
+            .about("Check that fly, spruce, jq and .flyrc are set up")
+            .arg(format_arg()))
+        .subcommand(Command::new("ci")
+            .about("Manage CI configuration")
+            .arg(Arg::new("team")
+                .long("team")
+                .value_name("TEAM")
+                .global(true)
+                .help("Concourse team for fly calls (defaults to the target's team)"))
+            .arg(Arg::new("env")
+                .long("env")
+                .value_name("ENV")
+                .value_parser(ENVIRONMENTS.to_vec())
+                .global(true)
+                .help("Merge kit pipelines with settings-<ENV>.yml or settings/<ENV>.yml when it exists"))
+            .arg(Arg::new("settings")
+                .long("settings")
+                .value_name("FILE")
+                .global(true)
+                .help("Settings file, relative to each kit's ci/, to merge kit pipelines with"))
+            .arg(Arg::new("dry-run")
+                .long("dry-run")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Print the fly commands that would change something instead of running them"))
+            .subcommand(Command::new("status")
+                .about("Show CI status for every kit")
+                .arg(format_arg())
+                .arg(kit_filter_arg())
+                .arg(exclude_kit_arg())
+                .arg(Arg::new("only-failing")
+                    .long("only-failing")
+                    .action(ArgAction::SetTrue)
+                    .help("Only list kits whose last build failed or whose status errored"))
+                .arg(Arg::new("wide")
+                    .long("wide")
+                    .action(ArgAction::SetTrue)
+                    .help("Include the last build time column"))
+                .arg(Arg::new("cache-ttl")
+                    .long("cache-ttl")
+                    .value_name("SECONDS")
+                    .value_parser(clap::value_parser!(u64))
+                    .default_value("0")
+                    .help("Reuse cached statuses younger than this (0 disables the cache)"))
+                .arg(Arg::new("no-cache")
+                    .long("no-cache")
+                    .action(ArgAction::SetTrue)
+                    .help("Discard the status cache before fetching"))
+                .arg(Arg::new("no-spruce")
+                    .long("no-spruce")
+                    .action(ArgAction::SetTrue)
+                    .help("Use the genesis-kit-<kit> pipeline name when fly has it, skipping the spruce merge"))
+                .arg(Arg::new("concurrency")
+                    .long("concurrency")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("4")
+                    .help("Maximum kits fetched at once (1 fetches sequentially)"))
+                .arg(Arg::new("refresh")
+                    .long("refresh")
+                    .value_name("SECONDS")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .help("Redraw the status every SECONDS until Ctrl-C")))
+            .subcommand(Command::new("pause")
+                .about("Pause a kit's pipeline")
+                .arg(kit_arg()))
+            .subcommand(Command::new("unpause")
+                .about("Unpause a kit's pipeline")
+                .arg(kit_arg()))
+            .subcommand(Command::new("expose")
+                .about("Make a kit's pipeline publicly visible")
+                .arg(kit_arg()))
+            .subcommand(Command::new("hide")
+                .about("Make a kit's pipeline private")
+                .arg(kit_arg()))
+            .subcommand(Command::new("trigger")
+                .about("Trigger a kit's test job and watch it")
+                .arg(kit_arg())
+                .arg(Arg::new("all")
+                    .long("all")
+                    .action(ArgAction::SetTrue)
+                    .conflicts_with_all(["kit", "tail", "fly-args"])
+                    .help("Trigger every kit's job without watching, then list the build numbers"))
+                .arg(kit_filter_arg().requires("all"))
+                .arg(exclude_kit_arg().requires("all"))
+                .arg(Arg::new("concurrency")
+                    .long("concurrency")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .requires("all")
+                    .help("With --all, maximum kits triggered at once [default: 4]"))
+                .arg(fly_args_arg("trigger-job"))
+                .arg(Arg::new("force")
+                    .long("force")
+                    .action(ArgAction::SetTrue)
+                    .help("Trigger even if a build is already running"))
+                .arg(Arg::new("check-resources")
+                    .long("check-resources")
+                    .action(ArgAction::SetTrue)
+                    .help("First warn about resources that errored or haven't been checked in a day"))
+                .arg(tail_arg()))
+            .subcommand(Command::new("watch")
+                .about("Stream a kit's running build, or show how the last one ended")
+                .arg(kit_arg())
+                .arg(tail_arg()))
+            .subcommand(Command::new("logs")
+                .about("Show the output of a kit's latest build, or of --build")
+                .arg(kit_arg())
+                .arg(Arg::new("build")
+                    .long("build")
+                    .value_name("NUMBER")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Build number to show instead of the latest"))
+                .arg(tail_arg()))
+            .subcommand(Command::new("diff-config")
+                .about("Diff a kit's locally merged pipeline against the one set in Concourse")
+                .arg(kit_arg())
+                .arg(Arg::new("pipeline")
+                    .long("pipeline")
+                    .value_name("NAME")
+                    .help("Pipeline name (defaults to the kit's meta or naming convention)"))
+                .arg(Arg::new("run-scripts")
+                    .long("run-scripts")
+                    .action(ArgAction::SetTrue)
+                    .help("Run the kit's build scripts before merging (hooks never run here)")))
+            .subcommand(Command::new("jobs")
+                .about("List the jobs in a kit's pipeline")
+                .arg(kit_arg())
+                .arg(format_arg()))
+            .subcommand(Command::new("update")
+                .about("Edit the live pipeline config, review the diff and upload it")
+                .arg(kit_arg())
+                .arg(fly_args_arg("set-pipeline"))
+                .arg(Arg::new("file")
+                    .long("file")
+                    .value_name("PATH")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Use an already-edited pipeline file instead of opening $EDITOR"))
+)
+            .subcommand(Command::new("configure")
+                .about("Change single values in a kit's live pipeline, review the diff and upload it")
+                .arg(kit_arg())
+                .arg(fly_args_arg("set-pipeline"))
+                .arg(Arg::new("set-var")
+                    .long("set-var")
+                    .value_name("NAME=VALUE")
+                    .action(ArgAction::Append)
+                    .required(true)
+                    .value_parser(name_value_pair)
+                    .help("Set the dotted path NAME (list entries by name) to VALUE; repeatable"))
+                .arg(Arg::new("pipeline")
+                    .long("pipeline")
+                    .value_name("NAME")
+                    .help("Pipeline name (defaults to the kit's meta or naming convention)"))
+)
+            .subcommand(Command::new("set-pipeline")
+                .about("Upload a finished pipeline file for a kit, skipping the spruce merge")
+                .arg(kit_arg())
+                .arg(fly_args_arg("set-pipeline"))
+                .arg(Arg::new("file")
+                    .long("file")
+                    .alias("from-file")
+                    .value_name("PATH")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Pipeline YAML to upload"))
+                .arg(Arg::new("pipeline")
+                    .long("pipeline")
+                    .value_name("NAME")
+                    .help("Pipeline name (defaults to the kit's meta or naming convention)"))
+                .arg(Arg::new("target")
+                    .long("target")
+                    .value_name("TARGET")
+                    .help("fly target to upload to (defaults to the CI target)"))))
         .get_matches();
 
+    let log_format = cli.get_one::<String>("log-format").cloned()
+        .or_else(|| std::env::var("GKM_LOG_FORMAT").ok())
+        .unwrap_or_else(|| String::from("human"));
+    init_logger(&log_format)?;
+    process::set_verbose(cli.get_flag("verbose"));
+    ui::set_quiet(cli.get_flag("quiet"));
+    ui::set_interactive(if cli.get_flag("interactive") {
+        Some(true)
+    } else if cli.get_flag("no-interactive") {
+        Some(false)
+    } else {
+        None
+    });
+    if cli.get_flag("clean-env") {
+        process::set_clean_env(cli.get_many::<String>("pass-env").unwrap_or_default().cloned().collect());
+    }
+    if cli.get_flag("no-color") || config::config().theme == Theme::None {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+    if let Some(prefix) = cli.get_one::<String>("pipeline-prefix") {
+        kits::set_pipeline_prefix(prefix);
+    }
+    if let Some(root) = cli.get_one::<PathBuf>("kit-root") {
+        kits::set_kit_root(root)?;
+    }
+
+    let mut ui = GenesisKitUI::new();
+    if !cli.get_flag("no-memory") {
+        ui = ui.with_memory();
+    }
+    // `repipe --print` and json/yaml output are meant to be piped, so keep the banner off stdout
+    let printing = match cli.subcommand() {
+        Some(("repipe", m)) => m.get_flag("print"),
+        Some(("ci", ci)) => ci.subcommand()
+            .and_then(|(_, m)| m.try_get_one::<String>("format").ok().flatten())
+            .is_some_and(|format| format != "table"),
+        Some(("doctor", m)) => m.get_one::<String>("format").is_some_and(|format| format != "table"),
+        _ => false,
+    };
+    let no_banner = cli.get_flag("no-banner") || std::env::var_os("GKM_NO_BANNER").is_some();
+    if !printing && !no_banner {
+        ui.display_welcome()?;
+    }
+
+    if let Some(("repipe", m)) = cli.subcommand() {
+        process::set_dry_run(m.get_count("dry-run") > 0);
+    }
+
     match cli.subcommand() {
-        Some(("repipe", _)) => ui.repipe_interactive(),
-        Some(("template", _)) => ui.manage_template_version().await?,
-        Some(("ci", _)) => ui.manage_ci().await?,
+        Some(("repipe", m)) if m.get_flag("all") => ui.repipe_all(repipe_options(m))?,
+        Some(("repipe", m)) => ui.repipe_interactive(repipe_options(m))?,
+        Some(("template", t)) => match t.subcommand() {
+            Some(("diff", m)) => ui.preview_template_version(
+                m.get_one::<String>("kit").map(String::as_str),
+                m.get_one::<String>("version").map(String::as_str).unwrap_or_default(),
+            )?,
+            _ => ui.manage_template_version().await?,
+        },
+        Some(("init", m)) => ui.init_kit(InitOptions {
+            kit: m.get_one::<String>("kit").cloned(),
+            target: m.get_one::<String>("target").cloned(),
+            team: m.get_one::<String>("team").cloned(),
+            force: m.get_flag("force"),
+        })?,
+        Some(("doctor", m)) => ui.run_doctor(m.get_one::<String>("format")
+            .and_then(|format| format.parse().ok())
+            .unwrap_or_default())?,
+        Some(("ci", ci)) => {
+            let (action, m) = ci.subcommand().unwrap_or(("", ci));
+            let options = ci_options(m);
+            process::set_dry_run(options.dry_run);
+            kits::set_settings_lookup(options.settings_lookup());
+            options.warn_unknown_team();
+            let target = options.target.as_deref().unwrap_or(CI_TARGET);
+            let fly = ProcessFlyClient::new(target, options.team.clone());
+
+            match action {
+                "status" => ui.view_ci_status(&options, &fly).await?,
+                "pause" => ui.set_pipeline_paused(&options, &fly, true).await?,
+                "unpause" => ui.set_pipeline_paused(&options, &fly, false).await?,
+                "expose" => ui.set_pipeline_exposed(&options, &fly, true).await?,
+                "hide" => ui.set_pipeline_exposed(&options, &fly, false).await?,
+                "trigger" => ui.trigger_ci_build(&options, &fly).await?,
+                "watch" => ui.watch_ci_build(&options, &fly).await?,
+                "logs" => ui.view_ci_logs(&options, &fly).await?,
+                "jobs" => ui.list_ci_jobs(&options, &fly).await?,
+                "diff-config" => ui.diff_ci_config(&options, &fly).await?,
+                "update" => ui.update_ci_config(&options, &fly).await?,
+                "set-pipeline" => ui.set_ci_pipeline(&options, &fly).await?,
+                "configure" => ui.configure_ci_vars(&options, &fly).await?,
+                _ => ui.manage_ci(&options, &fly).await?,
+            }
+        }
         _ => {
             println!("Please specify a command. Use --help for usage information.");
         }
     }
 
+    ui.save_memory();
+    Ok(())
+}
+
+/// Human-readable logs by default; `json` emits one JSON object per record for log aggregators.
+fn init_logger(format: &str) -> Result<()> {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    match format {
+        "human" => {
+            builder
+                .format_timestamp(Some(env_logger::TimestampPrecision::Seconds))
+                .format_module_path(true);
+        }
+        "json" => {
+            builder.format(|buf, record| {
+                let line = serde_json::json!({
+                    "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                    "level": record.level().to_string(),
+                    "module": record.module_path().unwrap_or_default(),
+                    "message": record.args().to_string(),
+                });
+                writeln!(buf, "{}", line)
+            });
+        }
+        other => anyhow::bail!("Unknown log format '{}'; expected 'human' or 'json'", other),
+    }
+    builder.init();
     Ok(())
-}
\ No newline at end of file
+}
+
+fn repipe_options(m: &ArgMatches) -> RepipeOptions {
+    // Last of --expose/--no-expose wins; neither leaves the decision to meta
+    let expose = if m.get_flag("expose") {
+        Some(true)
+    } else if m.get_flag("no-expose") {
+        Some(false)
+    } else {
+        None
+    };
+
+    RepipeOptions {
+        validate: m.get_count("validate"),
+        dry_run: m.get_count("dry-run"),
+        pause: m.get_flag("pause"),
+        expose,
+        open_browser: m.get_count("open"),
+        yes: m.get_flag("yes"),
+        fly_path: m.get_one::<String>("fly").cloned(),
+        debug: m.get_flag("debug"),
+        no_cleanup: m.get_flag("no-cleanup"),
+        output_dir: m.get_one::<PathBuf>("output-dir").and_then(|dir| std::path::absolute(dir).ok()),
+        target: m.get_one::<String>("target").cloned(),
+        print: m.get_flag("print"),
+        pipeline: m.get_one::<String>("pipeline").cloned(),
+        validate_only: m.get_flag("validate-only"),
+        native_jobs: m.get_flag("native-jobs"),
+        skip_scripts: m.get_flag("skip-scripts"),
+        no_hooks: m.get_flag("no-hooks"),
+        parallel_scripts: m.get_flag("parallel-scripts"),
+        skip_checks: m.get_flag("skip-checks"),
+        fly_args: m.get_many::<String>("fly-args").into_iter().flatten().cloned().collect(),
+        deploy_file: m.get_one::<String>("deploy-file").cloned(),
+        strict_target: m.get_flag("strict-target"),
+        skip_flyrc_check: m.get_flag("skip-flyrc-check"),
+        check_creds: m.get_flag("check-creds"),
+        redact: m.get_flag("redact"),
+        dump_meta: m.get_flag("dump-meta") || m.get_flag("dump-meta-only"),
+        dump_meta_only: m.get_flag("dump-meta-only"),
+        instance_vars: m.get_many::<(String, String)>("instance-var").into_iter().flatten().cloned().collect(),
+        strict: m.get_flag("strict"),
+        env: m.get_one::<String>("env").cloned(),
+        settings: m.get_one::<String>("settings").cloned(),
+        since: m.get_one::<String>("since").cloned(),
+        kit_filter: m.get_one::<regex::Regex>("kit-filter").cloned(),
+        vault_addr: m.get_one::<String>("vault-addr").cloned(),
+        vault_token: m.get_one::<String>("vault-token").cloned(),
+        exclude_kits: m.get_many::<String>("exclude-kit").into_iter().flatten().cloned().collect(),
+        spruce_args: m.get_many::<String>("spruce-arg").into_iter().flatten().cloned().collect(),
+        prune: m.get_many::<String>("prune").into_iter().flatten().cloned().collect(),
+        ..Default::default()
+    }
+}
+
+/// Parses `NAME=VALUE`, splitting at the first `=`.
+fn name_value_pair(pair: &str) -> Result<(String, String), String> {
+    pair.split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| format!("'{}' isn't NAME=VALUE", pair))
+}
+
+/// Everything after `--`, handed to fly untouched.
+fn fly_args_arg(subcommand: &str) -> Arg {
+    Arg::new("fly-args")
+        .num_args(1..)
+        .last(true)
+        .allow_hyphen_values(true)
+        .value_name("FLY ARGS")
+        .help(format!("Extra args for `fly {}`, after --", subcommand))
+}
+
+fn kit_arg() -> Arg {
+    Arg::new("kit")
+        .long("kit")
+        .value_name("KIT")
+        .help("Kit to operate on (prompts when omitted)")
+}
+
+fn kit_filter_arg() -> Arg {
+    Arg::new("kit-filter")
+        .long("kit-filter")
+        .value_name("REGEX")
+        .value_parser(|pattern: &str| regex::Regex::new(pattern).map_err(|e| e.to_string()))
+        .help("Only include kits whose name matches REGEX")
+}
+
+fn exclude_kit_arg() -> Arg {
+    Arg::new("exclude-kit")
+        .long("exclude-kit")
+        .value_name("KIT")
+        .action(ArgAction::Append)
+        .help("Skip this kit (repeatable)")
+}
+
+fn format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .value_name("FORMAT")
+        .value_parser(OutputFormat::NAMES)
+        .default_value("table")
+        .help("Output format")
+}
+
+fn tail_arg() -> Arg {
+    Arg::new("tail")
+        .long("tail")
+        .value_name("N")
+        .value_parser(clap::value_parser!(usize))
+        .help("Only show the last N lines of build output, once the build ends")
+}
+
+fn ci_options(m: &ArgMatches) -> CiOptions {
+    CiOptions {
+        kit: m.try_get_one::<String>("kit").ok().flatten().cloned(),
+        file: m.try_get_one::<PathBuf>("file").ok().flatten().cloned(),
+        dry_run: flag(m, "dry-run"),
+        wide: flag(m, "wide"),
+        cache_ttl: m.try_get_one::<u64>("cache-ttl").ok().flatten().copied().unwrap_or(0),
+        no_cache: flag(m, "no-cache"),
+        no_spruce: flag(m, "no-spruce"),
+        team: m.try_get_one::<String>("team").ok().flatten().cloned(),
+        concurrency: m.try_get_one::<u64>("concurrency").ok().flatten().map_or(0, |n| *n as usize),
+        force: flag(m, "force"),
+        check_resources: flag(m, "check-resources"),
+        all: flag(m, "all"),
+        only_failing: flag(m, "only-failing"),
+        run_scripts: flag(m, "run-scripts"),
+        build: m.try_get_one::<u64>("build").ok().flatten().copied(),
+        kit_filter: m.try_get_one::<regex::Regex>("kit-filter").ok().flatten().cloned(),
+        exclude_kits: m.try_get_many::<String>("exclude-kit").ok().flatten().into_iter().flatten().cloned().collect(),
+        pipeline: m.try_get_one::<String>("pipeline").ok().flatten().cloned(),
+        target: m.try_get_one::<String>("target").ok().flatten().cloned(),
+        env: m.try_get_one::<String>("env").ok().flatten().cloned(),
+        settings: m.try_get_one::<String>("settings").ok().flatten().cloned(),
+        refresh: m.try_get_one::<u64>("refresh").ok().flatten().copied().unwrap_or(0),
+        tail: m.try_get_one::<usize>("tail").ok().flatten().copied(),
+        skip_checks: flag(m, "skip-checks"),
+        fly_args: m.try_get_many::<String>("fly-args").ok().flatten().into_iter().flatten().cloned().collect(),
+        set_vars: m.try_get_many::<(String, String)>("set-var").ok().flatten().into_iter().flatten().cloned().collect(),
+        format: m.try_get_one::<String>("format").ok().flatten()
+            .and_then(|format| format.parse().ok())
+            .unwrap_or_default(),
+    }
+}
+
+/// Reads a SetTrue flag that only some subcommands define.
+fn flag(m: &ArgMatches, id: &str) -> bool {
+    m.try_get_one::<bool>(id).ok().flatten().copied().unwrap_or(false)
+}
@@ -0,0 +1,279 @@
+// src/process.rs
+use log::info;
+use std::{
+    env,
+    ffi::OsString,
+    future::Future,
+    io,
+    process::{Child, Command, ExitStatus, Output},
+    sync::{atomic::{AtomicBool, Ordering}, OnceLock},
+};
+use tokio::process::{Child as AsyncChild, Command as AsyncCommand};
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+/// Set when children get a curated environment; holds the extra names to pass through.
+static CLEAN_ENV: OnceLock<Vec<String>> = OnceLock::new();
+
+const SECRET_FLAGS: &[&str] = &["--password", "--token", "--client-secret"];
+const SECRET_PATTERNS: &[&str] = &["password", "passwd", "secret", "token", "credential", "private_key"];
+const REDACTED: &str = "********";
+
+const CLEAN_ENV_VARS: &[&str] = &["PATH", "HOME"];
+const CLEAN_ENV_PREFIXES: &[&str] = &["CONCOURSE_", "VAULT_"];
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+/// Makes [`RunExt::run_change`] and friends print commands instead of running them.
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Under `--dry-run`, prints `cmd` (secrets redacted) instead of running it and
+/// returns true; callers report a synthetic success.
+pub fn skip_for_dry_run(cmd: &Command) -> bool {
+    if !is_dry_run() {
+        return false;
+    }
+    println!("Would run: {}", describe(cmd));
+    true
+}
+
+#[cfg(unix)]
+fn success_status() -> ExitStatus {
+    std::os::unix::process::ExitStatusExt::from_raw(0)
+}
+
+#[cfg(windows)]
+fn success_status() -> ExitStatus {
+    std::os::windows::process::ExitStatusExt::from_raw(0)
+}
+
+/// What a command skipped by `--dry-run` reports.
+fn dry_run_output() -> Output {
+    Output { status: success_status(), stdout: Vec::new(), stderr: Vec::new() }
+}
+
+/// Runs every later child with only `PATH`, `HOME`, the `CONCOURSE_*`/`VAULT_*`
+/// vars and the names in `pass` from our environment, instead of all of it.
+pub fn set_clean_env(pass: Vec<String>) {
+    let _ = CLEAN_ENV.set(pass);
+}
+
+fn is_allowed_var(name: &str, pass: &[String]) -> bool {
+    CLEAN_ENV_VARS.contains(&name)
+        || CLEAN_ENV_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+        || pass.iter().any(|p| p == name)
+}
+
+/// The vars `cmd` will see under `--clean-env`: the allowlisted parent vars,
+/// then whatever was set on `cmd` itself.
+fn clean_env(cmd: &Command, pass: &[String]) -> Vec<(OsString, Option<OsString>)> {
+    let inherited = env::vars_os()
+        .filter(|(name, _)| name.to_str().is_some_and(|name| is_allowed_var(name, pass)))
+        .map(|(name, value)| (name, Some(value)));
+    let explicit = cmd.get_envs().map(|(name, value)| (name.to_os_string(), value.map(|v| v.to_os_string())));
+    inherited.chain(explicit).collect()
+}
+
+/// Narrows `cmd`'s environment when `--clean-env` is on.
+fn prepare(cmd: &mut Command) {
+    if let Some(pass) = CLEAN_ENV.get() {
+        restrict_env(cmd, pass);
+    }
+}
+
+/// Replaces `cmd`'s environment with [`clean_env`]'s.
+fn restrict_env(cmd: &mut Command, pass: &[String]) {
+    let vars = clean_env(cmd, pass);
+    cmd.env_clear();
+    for (name, value) in vars {
+        match value {
+            Some(value) => cmd.env(name, value),
+            None => cmd.env_remove(name),
+        };
+    }
+}
+
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_PATTERNS.iter().any(|pattern| name.contains(pattern))
+}
+
+/// Renders a command line with password flags and secret-looking `key=value` pairs masked.
+pub fn describe(cmd: &Command) -> String {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let args: Vec<String> = cmd.get_args().map(|a| a.to_string_lossy().into_owned()).collect();
+    // `fly login -p` is the password, everywhere else `-p` is the pipeline name
+    let is_login = args.iter().any(|a| a == "login");
+
+    let mut rendered = vec![program];
+    let mut mask_next = false;
+    for arg in args {
+        if mask_next {
+            rendered.push(REDACTED.to_string());
+            mask_next = false;
+            continue;
+        }
+
+        if SECRET_FLAGS.contains(&arg.as_str()) || (is_login && arg == "-p") {
+            mask_next = true;
+            rendered.push(arg);
+        } else if let Some((key, _)) = arg.split_once('=') {
+            if is_secret_name(key) {
+                rendered.push(format!("{}={}", key, REDACTED));
+            } else {
+                rendered.push(arg);
+            }
+        } else {
+            rendered.push(arg);
+        }
+    }
+
+    rendered.join(" ")
+}
+
+fn trace(cmd: &mut Command) {
+    prepare(cmd);
+    if VERBOSE.load(Ordering::Relaxed) {
+        info!("Running: {}", describe(cmd));
+    }
+}
+
+/// Runs std commands through the shared tracing and environment handling.
+pub trait RunExt {
+    fn run_output(&mut self) -> io::Result<Output>;
+    /// `run_output` for commands that change something; under `--dry-run`
+    /// they're printed rather than run.
+    fn run_change(&mut self) -> io::Result<Output>;
+    fn run_status(&mut self) -> io::Result<ExitStatus>;
+    fn run_spawn(&mut self) -> io::Result<Child>;
+}
+
+impl RunExt for Command {
+    fn run_output(&mut self) -> io::Result<Output> {
+        trace(self);
+        self.output()
+    }
+
+    fn run_change(&mut self) -> io::Result<Output> {
+        prepare(self);
+        if skip_for_dry_run(self) {
+            return Ok(dry_run_output());
+        }
+        self.run_output()
+    }
+
+    fn run_status(&mut self) -> io::Result<ExitStatus> {
+        trace(self);
+        self.status()
+    }
+
+    fn run_spawn(&mut self) -> io::Result<Child> {
+        trace(self);
+        self.spawn()
+    }
+}
+
+/// Runs tokio commands through the shared tracing and environment handling.
+pub trait AsyncRunExt {
+    fn run_output(&mut self) -> impl Future<Output = io::Result<Output>> + Send + '_;
+    /// See [`RunExt::run_change`].
+    fn run_change(&mut self) -> impl Future<Output = io::Result<Output>> + Send + '_;
+    fn run_status(&mut self) -> impl Future<Output = io::Result<ExitStatus>> + Send + '_;
+    fn run_spawn(&mut self) -> io::Result<AsyncChild>;
+}
+
+impl AsyncRunExt for AsyncCommand {
+    fn run_output(&mut self) -> impl Future<Output = io::Result<Output>> + Send + '_ {
+        trace(self.as_std_mut());
+        self.output()
+    }
+
+    fn run_change(&mut self) -> impl Future<Output = io::Result<Output>> + Send + '_ {
+        prepare(self.as_std_mut());
+        let skip = skip_for_dry_run(self.as_std());
+        async move {
+            if skip {
+                Ok(dry_run_output())
+            } else {
+                self.run_output().await
+            }
+        }
+    }
+
+    fn run_status(&mut self) -> impl Future<Output = io::Result<ExitStatus>> + Send + '_ {
+        trace(self.as_std_mut());
+        self.status()
+    }
+
+    fn run_spawn(&mut self) -> io::Result<AsyncChild> {
+        trace(self.as_std_mut());
+        self.spawn()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn described(args: &[&str]) -> String {
+        let mut cmd = Command::new("fly");
+        cmd.args(args);
+        describe(&cmd)
+    }
+
+    #[test]
+    fn describe_masks_secrets() {
+        let cases: &[&[&str]] = &[
+            &["-t", "ci", "login", "-u", "admin", "-p", "hunter2"],
+            &["-t", "ci", "login", "--password=hunter2"],
+            &["-t", "ci", "login", "--token", "hunter2"],
+            &["-t", "ci", "login", "--client-secret", "hunter2"],
+            &["set-pipeline", "--var", "vault_token=hunter2"],
+        ];
+        for args in cases {
+            let line = described(args);
+            assert!(line.contains("***"), "{} isn't masked", line);
+            assert!(!line.contains("hunter2"), "{} leaks the secret", line);
+        }
+    }
+
+    #[test]
+    fn describe_keeps_pipeline_flag_outside_login() {
+        assert_eq!(described(&["-t", "ci", "pause-pipeline", "-p", "kit"]), "fly -t ci pause-pipeline -p kit");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn clean_env_passes_only_allowlisted_vars() {
+        let _env = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let vars = [("CONCOURSE_TARGET", "ci"), ("VAULT_ADDR", "https://vault"), ("GKM_PASSED", "yes"), ("GKM_LEAKED", "no")];
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        let mut cmd = Command::new("env");
+        cmd.env("GKM_EXPLICIT", "set").env_remove("VAULT_ADDR");
+        restrict_env(&mut cmd, &[String::from("GKM_PASSED")]);
+        let output = cmd.output().unwrap();
+        for (name, _) in vars {
+            env::remove_var(name);
+        }
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let names: Vec<&str> = stdout.lines().filter_map(|line| line.split_once('=')).map(|(name, _)| name).collect();
+        for name in ["CONCOURSE_TARGET", "GKM_PASSED", "GKM_EXPLICIT"] {
+            assert!(names.contains(&name), "{} missing from {:?}", name, names);
+        }
+        assert!(!names.contains(&"GKM_LEAKED") && !names.contains(&"VAULT_ADDR"), "{:?}", names);
+        for name in names {
+            assert!(name == "GKM_EXPLICIT" || is_allowed_var(name, &[String::from("GKM_PASSED")]), "{} leaked", name);
+        }
+    }
+}
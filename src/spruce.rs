@@ -0,0 +1,316 @@
+// src/spruce.rs
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use serde::Deserialize;
+use regex::Regex;
+use std::{collections::BTreeMap, env, fs, path::PathBuf, process::{Command, Output, Stdio}, sync::OnceLock};
+use crate::process::RunExt;
+
+const SPRUCE_RELEASES: &str = "https://github.com/geofffranks/spruce/releases";
+
+/// Old spruce builds reject `--fallback-append` as an unknown flag.
+fn rejects_fallback_append(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("fallback-append")
+        && ["unknown flag", "unknown long flag", "unknown option", "flag provided but not defined"]
+            .iter()
+            .any(|msg| stderr.contains(msg))
+}
+
+/// Vault credentials for `(( vault ))` operators. Unset fields leave spruce
+/// to read `VAULT_ADDR`/`VAULT_TOKEN` from the environment as usual.
+#[derive(Debug, Clone, Default)]
+pub struct Vault {
+    pub addr: Option<String>,
+    pub token: Option<String>,
+}
+
+impl Vault {
+    fn apply(&self, cmd: &mut Command) {
+        if let Some(addr) = &self.addr {
+            cmd.env("VAULT_ADDR", addr);
+        }
+        if let Some(token) = &self.token {
+            cmd.env("VAULT_TOKEN", token);
+        }
+    }
+
+    fn addr(&self) -> Option<String> {
+        self.addr.clone().or_else(|| env::var("VAULT_ADDR").ok())
+    }
+
+    /// Whether spruce will have both an address and a token, counting the
+    /// environment and the `~/.vault-token` the vault CLI leaves behind.
+    pub fn is_configured(&self) -> bool {
+        let token = self.token.is_some()
+            || env::var_os("VAULT_TOKEN").is_some()
+            || dirs::home_dir().is_some_and(|home| home.join(".vault-token").is_file());
+        self.addr().is_some() && token
+    }
+}
+
+/// The operators spruce knows; other `(( name ... ))` calls fail the merge.
+const KNOWN_OPERATORS: &[&str] = &[
+    "awsparam", "awssecret", "base64", "base64-decode", "calc", "cartesian-product", "concat",
+    "defer", "empty", "file", "grab", "inject", "ips", "join", "keys", "load", "negate", "null",
+    "param", "prune", "shuffle", "sort", "static_ips", "stringify", "vault", "vault-try",
+];
+
+/// How often each spruce operator is called across `files`, e.g. `grab => 12`.
+/// Names spruce doesn't know are counted too, so callers can flag them.
+/// Concourse's own `((var))` placeholders have no space after the name and
+/// aren't counted.
+pub fn scan_operators(files: &[PathBuf]) -> BTreeMap<String, usize> {
+    static OPERATOR: OnceLock<Regex> = OnceLock::new();
+    let operator = OPERATOR.get_or_init(|| Regex::new(r"\(\(\s*([a-z][a-z0-9_-]*)\s+[^)]").unwrap());
+
+    let mut counts = BTreeMap::new();
+    for contents in files.iter().filter_map(|file| fs::read_to_string(file).ok()) {
+        for call in operator.captures_iter(&contents) {
+            *counts.entry(call[1].to_string()).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+pub fn is_known_operator(name: &str) -> bool {
+    KNOWN_OPERATORS.contains(&name)
+}
+
+/// spruce's ways of saying it couldn't talk to Vault.
+fn is_vault_unreachable(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("vault")
+        && ["connection refused", "no such host", "dial tcp", "i/o timeout", "permission denied", "missing client token"]
+            .iter()
+            .any(|msg| stderr.contains(msg))
+}
+
+/// Every `---`-separated document in `contents`, failing on the first malformed one.
+pub fn parse_documents(contents: &str) -> Result<Vec<serde_yaml::Value>> {
+    serde_yaml::Deserializer::from_str(contents)
+        .enumerate()
+        .map(|(i, doc)| serde_yaml::Value::deserialize(doc)
+            .with_context(|| format!("YAML document {} is malformed", i + 1)))
+        .collect()
+}
+
+/// Parses each input on its own, so a syntax error is pinned to
+/// `file:line:column` before spruce reports it less precisely.
+pub fn lint(files: &[PathBuf]) -> Result<()> {
+    for file in files {
+        let Ok(contents) = fs::read_to_string(file) else {
+            // Leave unreadable files for spruce to report
+            continue;
+        };
+        for (i, doc) in serde_yaml::Deserializer::from_str(&contents).enumerate() {
+            if let Err(e) = serde_yaml::Value::deserialize(doc) {
+                let at = e.location().map(|l| format!(":{}:{}", l.line(), l.column())).unwrap_or_default();
+                let document = if i > 0 { format!(" (document {})", i + 1) } else { String::new() };
+                bail!("{}{}: invalid YAML{}: {}", file.display(), at, document, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Flags [`merge`] sets itself; `--prune` is applied afterwards from repipe's `--prune`.
+const MERGE_FLAGS: &[&str] = &["--fallback-append", "--multi-doc", "--prune"];
+
+/// Checks `--spruce-arg`s are flags (`--flag` or `--flag=value`) that don't
+/// clash with the ones [`merge`] already passes.
+pub fn check_merge_args(extra: &[String]) -> Result<()> {
+    for arg in extra {
+        let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+        if !flag.starts_with('-') {
+            bail!("--spruce-arg '{}' isn't a flag; pass flags with values as --spruce-arg=--flag=value", arg);
+        }
+        if MERGE_FLAGS.contains(&flag) {
+            bail!("--spruce-arg '{}' conflicts with a flag gk already passes to spruce merge", arg);
+        }
+    }
+    Ok(())
+}
+
+/// Whether any input holds more than one YAML document, checking each parses.
+fn has_multiple_documents(files: &[PathBuf]) -> Result<bool> {
+    let mut multi = false;
+    for file in files {
+        let Ok(contents) = fs::read_to_string(file) else {
+            // Leave unreadable files for spruce to report
+            continue;
+        };
+        let documents = parse_documents(&contents)
+            .with_context(|| format!("Invalid YAML in {}", file.display()))?;
+        multi |= documents.len() > 1;
+    }
+    Ok(multi)
+}
+
+/// `spruce merge <append> <flags> <extra> <files>`, with the `--spruce-arg`s
+/// after gk's own flags so spruce sees them last.
+fn merge_command(append: &[&str], flags: &[&str], extra: &[String], files: &[PathBuf]) -> Command {
+    let mut cmd = Command::new("spruce");
+    cmd.arg("merge").args(append).args(flags).args(extra).args(files);
+    cmd
+}
+
+/// `spruce merge --fallback-append <files>`, retried once without the flag when
+/// the installed spruce predates it. Other merge failures come back as an
+/// unsuccessful `Output` for the caller to report.
+///
+/// Inputs with several `---` documents are merged with `--multi-doc`, so each
+/// document is layered in order as if it were its own file; otherwise spruce
+/// would only read the first.
+///
+/// `vault` is handed to spruce for `(( vault ))` lookups, and a failure to
+/// reach Vault is reported as such. `extra` flags go before the files,
+/// unchecked here; see [`check_merge_args`].
+pub fn merge(files: &[PathBuf], vault: &Vault, extra: &[String]) -> Result<Output> {
+    let multi_doc = has_multiple_documents(files)?;
+    if multi_doc {
+        debug!("Merging with --multi-doc");
+    }
+    let flags: &[&str] = if multi_doc { &["--multi-doc"] } else { &[] };
+
+    let mut cmd = merge_command(&["--fallback-append"], flags, extra, files);
+    vault.apply(&mut cmd);
+    let output = cmd.run_output()?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() && is_vault_unreachable(&stderr) {
+        bail!("spruce couldn't resolve (( vault )) references against {}: {}\nCheck --vault-addr/VAULT_ADDR and that your Vault token is valid",
+            vault.addr().unwrap_or_else(|| String::from("an unset VAULT_ADDR")), stderr.trim());
+    }
+    if output.status.success() || !rejects_fallback_append(&stderr) {
+        return Ok(output);
+    }
+
+    warn!("This spruce doesn't support --fallback-append; merging without it (lists will replace rather than append)");
+    let mut cmd = merge_command(&[], flags, extra, files);
+    vault.apply(&mut cmd);
+    let retry = cmd.run_output()?;
+
+    if !retry.status.success() {
+        bail!("spruce merge failed: {}\nYour spruce is too old for gk; please upgrade it from {}",
+            String::from_utf8_lossy(&retry.stderr).trim(), SPRUCE_RELEASES);
+    }
+    Ok(retry)
+}
+
+/// `spruce merge --skip-eval <files>`: layers the files without evaluating any
+/// `(( ))` operators, for patching configs that were already merged once.
+pub fn overlay(files: &[PathBuf]) -> Result<String> {
+    let output = Command::new("spruce")
+        .args(["merge", "--skip-eval"])
+        .args(files)
+        .run_output()
+        .context("Failed to run spruce")?;
+    if !output.status.success() {
+        bail!("spruce merge failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `yaml` with the top-level (or dotted) `keys` removed by `spruce merge --prune`,
+/// without evaluating anything again.
+pub fn prune(yaml: &str, keys: &[String]) -> Result<String> {
+    if keys.is_empty() {
+        return Ok(yaml.to_string());
+    }
+    let mut cmd = Command::new("spruce");
+    cmd.args(["merge", "--skip-eval"]);
+    for key in keys {
+        cmd.args(["--prune", key]);
+    }
+    let mut child = cmd.arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .run_spawn()
+        .context("Failed to run spruce")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        use std::io::Write;
+        stdin.write_all(yaml.as_bytes())?;
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("spruce --prune {} failed: {}", keys.join(" --prune "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_document_settings_are_merged_with_multi_doc() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.yml");
+        let settings = dir.path().join("settings.yml");
+        fs::write(&base, "meta: {kit: shield}\n").unwrap();
+        fs::write(&settings, "meta: {team: main}\n---\nmeta: {team: dev}\n").unwrap();
+
+        let documents = parse_documents(&fs::read_to_string(&settings).unwrap()).unwrap();
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[1]["meta"]["team"], "dev");
+        assert!(!has_multiple_documents(std::slice::from_ref(&base)).unwrap());
+        assert!(has_multiple_documents(&[base.clone(), settings.clone()]).unwrap());
+        lint(&[base, settings]).unwrap();
+    }
+
+    #[test]
+    fn malformed_overlay_among_valid_ones_is_named() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = [
+            ("base.yml", "jobs:\n  - name: test-kit\n"),
+            ("upstream.yml", "jobs:\n  - name: upstream\n    plan: [\n"),
+            ("settings.yml", "meta: {team: main}\n"),
+        ].into_iter()
+            .map(|(name, contents)| {
+                let path = dir.path().join(name);
+                fs::write(&path, contents).unwrap();
+                path
+            })
+            .collect();
+
+        let err = lint(&files).unwrap_err().to_string();
+        let at = format!("{}:", files[1].display());
+        assert!(err.starts_with(&at), "{}", err);
+        let location = err[at.len()..].split(": ").next().unwrap();
+        let (line, column) = location.split_once(':').unwrap();
+        assert!(line.parse::<usize>().unwrap() >= 3 && column.parse::<usize>().is_ok(), "{}", err);
+        assert!(!err.contains("base.yml") && !err.contains("settings.yml"), "{}", err);
+        lint(&[files[0].clone(), files[2].clone()]).unwrap();
+    }
+
+    #[test]
+    fn malformed_second_document_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = dir.path().join("settings.yml");
+        fs::write(&settings, "meta: {team: main}\n---\nmeta: [unclosed\n").unwrap();
+
+        let err = lint(std::slice::from_ref(&settings)).unwrap_err().to_string();
+        assert!(err.starts_with(&format!("{}:", settings.display())), "{}", err);
+        assert!(err.contains("(document 2)"), "{}", err);
+        let err = format!("{:#}", has_multiple_documents(&[settings]).unwrap_err());
+        assert!(err.contains("YAML document 2 is malformed"), "{}", err);
+    }
+
+    #[test]
+    fn spruce_args_pass_through_to_the_merge() {
+        let extra = [String::from("--cherry-pick=jobs"), String::from("--go-patch")];
+        check_merge_args(&extra).unwrap();
+        let files = [PathBuf::from("base.yml"), PathBuf::from("settings.yml")];
+        let cmd = merge_command(&["--fallback-append"], &["--multi-doc"], &extra, &files);
+        let args: Vec<_> = cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        assert_eq!(args, ["merge", "--fallback-append", "--multi-doc", "--cherry-pick=jobs", "--go-patch", "base.yml", "settings.yml"]);
+
+        for (arg, complaint) in [("jobs", "isn't a flag"), ("--prune=meta", "conflicts"), ("--multi-doc", "conflicts")] {
+            let err = check_merge_args(&[arg.to_string()]).unwrap_err().to_string();
+            assert!(err.contains(complaint), "{}: {}", arg, err);
+        }
+    }
+}
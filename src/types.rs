@@ -1,8 +1,15 @@
+use serde::{Deserialize, Serialize};
 use tabled::Tabled;
 
-#[derive(Debug, Tabled)]
+/// One row of `gk ci status`. JSON/YAML output uses the snake_case form of
+/// each table header (`kit_name`, `version`, `template_version`, `ci_status`,
+/// `last_build`, `drift`), always in that order; that schema is kept stable.
+/// Values are plain text, as cached; the table adds color when it renders them.
+#[derive(Debug, Clone, PartialEq, Tabled, Serialize, Deserialize)]
 pub struct KitStatus {
     #[tabled(rename = "Kit Name")]
+    // `name` is what older status caches hold
+    #[serde(rename = "kit_name", alias = "name")]
     pub name: String,
     #[tabled(rename = "Version")]
     pub version: String,
@@ -10,4 +17,77 @@ pub struct KitStatus {
     pub template_version: String,
     #[tabled(rename = "CI Status")]
     pub ci_status: String,
-}
\ No newline at end of file
+    #[tabled(rename = "Last Build")]
+    pub last_build: String,
+    #[tabled(rename = "Drift")]
+    #[serde(default)]
+    pub drift: String,
+}
+impl KitStatus {
+    /// Whether the kit's last build failed or its status couldn't be fetched.
+    pub fn is_failing(&self) -> bool {
+        let status = console::strip_ansi_codes(&self.ci_status);
+        status.starts_with("Failed") || status.starts_with("Error")
+    }
+}
+
+#[derive(Debug, Tabled, Serialize)]
+pub struct JobStatus {
+    #[tabled(rename = "Job")]
+    pub name: String,
+    #[tabled(rename = "Paused")]
+    pub paused: String,
+    #[tabled(rename = "Next Build")]
+    pub next_build: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::format::OutputFormat;
+
+    fn statuses() -> Vec<KitStatus> {
+        vec![
+            KitStatus {
+                name: String::from("bosh"),
+                version: String::from("1.2.0"),
+                template_version: String::from("1.3.0"),
+                ci_status: String::from("Passing"),
+                last_build: String::from("2m ago"),
+                drift: String::from("behind template"),
+            },
+            KitStatus {
+                name: String::from("vault"),
+                version: String::from("unknown"),
+                template_version: String::from("unknown"),
+                ci_status: String::from("Not Deployed"),
+                last_build: String::from("never"),
+                drift: String::from("-"),
+            },
+        ]
+    }
+
+    #[test]
+    fn kit_statuses_roundtrip_through_json() {
+        let json = OutputFormat::Json.serialize(&statuses()).unwrap().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value[0]["kit_name"], "bosh");
+        assert!(value[0].get("name").is_none());
+        assert_eq!(serde_json::from_str::<Vec<KitStatus>>(&json).unwrap(), statuses());
+    }
+
+    #[test]
+    fn kit_statuses_roundtrip_through_yaml() {
+        let yaml = OutputFormat::Yaml.serialize(&statuses()).unwrap().unwrap();
+        assert!(yaml.starts_with("- kit_name: bosh\n"), "{}", yaml);
+        assert_eq!(serde_yaml::from_str::<Vec<KitStatus>>(&yaml).unwrap(), statuses());
+    }
+
+    #[test]
+    fn old_caches_with_name_still_load() {
+        let cached = r#"[{"name": "bosh", "version": "1", "template_version": "1", "ci_status": "Passing", "last_build": "never"}]"#;
+        let statuses: Vec<KitStatus> = serde_json::from_str(cached).unwrap();
+        assert_eq!(statuses[0].name, "bosh");
+        assert_eq!(statuses[0].drift, "");
+    }
+}
@@ -0,0 +1,29 @@
+use console::style;
+use similar::{ChangeTag, TextDiff};
+use super::styles::palette;
+
+/// Renders a colored unified diff of `old` vs `new`, or `None` when they are identical.
+pub fn render_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> Option<String> {
+    if old == new {
+        return None;
+    }
+
+    let diff = TextDiff::from_lines(old, new);
+    let mut out = Vec::new();
+    out.push(style(format!("--- {}", old_label)).fg(palette().error).bold().to_string());
+    out.push(style(format!("+++ {}", new_label)).fg(palette().success).bold().to_string());
+
+    for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+        out.push(style(hunk.header()).fg(palette().info).to_string());
+        for change in hunk.iter_changes() {
+            let line = format!("{}{}", change.tag(), change.value().trim_end_matches('\n'));
+            out.push(match change.tag() {
+                ChangeTag::Delete => style(line).fg(palette().error).to_string(),
+                ChangeTag::Insert => style(line).fg(palette().success).to_string(),
+                ChangeTag::Equal => style(line).dim().to_string(),
+            });
+        }
+    }
+
+    Some(out.join("\n"))
+}
@@ -0,0 +1,43 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::str::FromStr;
+
+/// How listing commands print their rows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 3] = ["table", "json", "yaml"];
+
+    /// Serializes `rows` for the machine-readable formats; `None` for `Table`,
+    /// which each command lays out itself.
+    pub fn serialize<T: Serialize + ?Sized>(self, rows: &T) -> Result<Option<String>> {
+        Ok(match self {
+            OutputFormat::Table => None,
+            OutputFormat::Json => Some(serde_json::to_string_pretty(rows)?),
+            OutputFormat::Yaml => Some(serde_yaml::to_string(rows)?),
+        })
+    }
+
+    pub fn is_table(self) -> bool {
+        self == OutputFormat::Table
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            other => bail!("Unknown format '{}'; expected one of: {}", other, Self::NAMES.join(", ")),
+        }
+    }
+}
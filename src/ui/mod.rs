@@ -1,17 +1,103 @@
 pub mod styles;
 pub mod progress;
+pub mod diff;
+pub mod format;
 
 use console::Term;
+use std::io::Write;
 use dialoguer::theme::ColorfulTheme;
 use indicatif::MultiProgress;
-use crate::constants::LOGO;
-use anyhow::Result;
+use log::warn;
+use std::sync::{Mutex, OnceLock, atomic::{AtomicBool, Ordering}};
+use crate::{cache::LastUsed, constants::{LOGO, VERSION}};
+use anyhow::{bail, Result};
 use self::styles::*;
 
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// `--quiet`: drop the banner, headings and progress, keeping warnings, errors and results.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// `--interactive`/`--no-interactive`; unset leaves it to terminal detection.
+static INTERACTIVE: OnceLock<bool> = OnceLock::new();
+
+pub fn set_interactive(forced: Option<bool>) {
+    if let Some(forced) = forced {
+        let _ = INTERACTIVE.set(forced);
+    }
+}
+
+/// Whether prompts may be shown: as forced on the command line, otherwise
+/// when both stdin and stderr are terminals.
+pub fn is_interactive() -> bool {
+    resolve_interactive(INTERACTIVE.get().copied(), has_terminal)
+}
+
+fn has_terminal() -> bool {
+    Term::stderr().is_term() && std::io::IsTerminal::is_terminal(&std::io::stdin())
+}
+
+/// The `forced` mode, else what `terminal` detects.
+fn resolve_interactive(forced: Option<bool>, terminal: impl FnOnce() -> bool) -> bool {
+    forced.unwrap_or_else(terminal)
+}
+
+/// Fails up front when there's no terminal to prompt on, naming `hint` (how
+/// to run without the prompt) instead of letting dialoguer hit EOF.
+pub fn require_terminal(hint: &str) -> Result<()> {
+    check_terminal(INTERACTIVE.get().copied(), has_terminal, hint)
+}
+
+fn check_terminal(forced: Option<bool>, terminal: impl FnOnce() -> bool, hint: &str) -> Result<()> {
+    if forced == Some(false) {
+        bail!("Prompts are disabled by --no-interactive; pass {} instead", hint);
+    }
+    if !resolve_interactive(forced, terminal) {
+        bail!("No input available for an interactive prompt; run non-interactively with {}", hint);
+    }
+    Ok(())
+}
+
+/// Gives a prompt that hit EOF or lost its terminal the same guidance as [`require_terminal`].
+pub fn prompt_error(e: dialoguer::Error, hint: &str) -> anyhow::Error {
+    let dialoguer::Error::IO(io) = &e;
+    match io.kind() {
+        std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::NotConnected => anyhow::anyhow!(
+            "No input available for an interactive prompt; run non-interactively with {}", hint),
+        _ => e.into(),
+    }
+}
+
+/// `println!` for output that is only commentary (headings, progress notes),
+/// which `--quiet` silences. Printed above any active progress bars.
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::ui::is_quiet() {
+            let line = format!($($arg)*);
+            $crate::ui::progress::suspend(|| println!("{}", line));
+        }
+    };
+}
+
 pub struct GenesisKitUI {
     pub term: Term,
     pub multi_progress: MultiProgress,
     pub theme: ColorfulTheme,
+    /// Last-used kit/target, or `None` under `--no-memory`.
+    memory: Option<Mutex<LastUsed>>,
+}
+
+impl Default for GenesisKitUI {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GenesisKitUI {
@@ -19,22 +105,110 @@ impl GenesisKitUI {
         Self {
             term: Term::stdout(),
             multi_progress: MultiProgress::new(),
-            theme: ColorfulTheme::default(),
+            theme: styles::dialoguer_theme(),
+            memory: None,
+        }
+    }
+
+    /// Remembers the kit and target between runs.
+    pub fn with_memory(mut self) -> Self {
+        self.memory = Some(Mutex::new(LastUsed::load()));
+        self
+    }
+
+    fn last_used(&self) -> Option<LastUsed> {
+        self.memory.as_ref().map(|m| m.lock().unwrap().clone())
+    }
+
+    /// Index of the last-used kit in `kits`, for a `Select` default.
+    pub fn last_kit_index(&self, kits: &[String]) -> usize {
+        self.last_used()
+            .and_then(|last| last.kit)
+            .and_then(|kit| kits.iter().position(|k| *k == kit))
+            .unwrap_or(0)
+    }
+
+    pub fn last_target(&self) -> Option<String> {
+        self.last_used().and_then(|last| last.target)
+    }
+
+    pub fn remember_kit(&self, kit: &str) {
+        if let Some(memory) = &self.memory {
+            memory.lock().unwrap().kit = Some(kit.to_string());
+        }
+    }
+
+    pub fn remember_target(&self, target: &str) {
+        if let Some(memory) = &self.memory {
+            memory.lock().unwrap().target = Some(target.to_string());
+        }
+    }
+
+    /// Writes the remembered choices; call only once the command has succeeded.
+    pub fn save_memory(&self) {
+        if let Some(last) = self.last_used() {
+            if let Err(e) = last.save() {
+                warn!("Could not save last-used kit and target: {:#}", e);
+            }
         }
     }
 
     pub fn display_welcome(&self) -> Result<()> {
-        self.term.clear_screen()?;
-        println!("{}", style_logo(LOGO));
-        println!("{}", heading("Genesis Kit Manager - DevOps Automation Tools"));
-        println!("{}\n", style_version("Version 1.0.0"));
-        
-        println!("{}", heading("Available Commands:"));
-        println!("  {} - {}", command("gk repipe"), info("Update Concourse pipelines"));
-        println!("  {} - {}", command("gk template"), info("Manage kit template versions"));
-        println!("  {} - {}", command("gk ci"), info("Manage CI configuration"));
-        println!();
-        
+        if !is_quiet() {
+            self.term.clear_screen()?;
+        }
+        write_welcome(&mut std::io::stdout(), is_quiet())?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// The welcome banner, written to `out`; nothing at all when `quiet`.
+fn write_welcome(out: &mut impl Write, quiet: bool) -> std::io::Result<()> {
+    if quiet {
+        return Ok(());
+    }
+    writeln!(out, "{}\n", style_logo(LOGO.trim_end()))?;
+    writeln!(out, "{}", heading("Genesis Kit Manager - DevOps Automation Tools"))?;
+    writeln!(out, "{}\n", style_version(&format!("Version {}", VERSION)))?;
+
+    writeln!(out, "{}", heading("Available Commands:"))?;
+    writeln!(out, "  {} - {}", command("gk repipe"), info("Update Concourse pipelines"))?;
+    writeln!(out, "  {} - {}", command("gk template"), info("Manage kit template versions"))?;
+    writeln!(out, "  {} - {}", command("gk ci"), info("Manage CI configuration"))?;
+    writeln!(out, "  {} - {}", command("gk doctor"), info("Check the local toolchain and fly login"))?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forced_modes_override_terminal_detection() {
+        for terminal in [true, false] {
+            assert!(resolve_interactive(Some(true), || terminal));
+            assert!(!resolve_interactive(Some(false), || terminal));
+            assert_eq!(resolve_interactive(None, || terminal), terminal);
+        }
+
+        assert!(check_terminal(Some(true), || false, "--kit").is_ok());
+        assert!(check_terminal(None, || true, "--kit").is_ok());
+        let forced_off = check_terminal(Some(false), || true, "--kit").unwrap_err();
+        assert_eq!(forced_off.to_string(), "Prompts are disabled by --no-interactive; pass --kit instead");
+        let no_terminal = check_terminal(None, || false, "--kit").unwrap_err();
+        assert_eq!(no_terminal.to_string(), "No input available for an interactive prompt; run non-interactively with --kit");
+    }
+
+    #[test]
+    fn quiet_mode_omits_the_banner() {
+        let mut loud = Vec::new();
+        write_welcome(&mut loud, false).unwrap();
+        let loud = console::strip_ansi_codes(std::str::from_utf8(&loud).unwrap()).into_owned();
+        assert!(loud.contains(&format!("Version {}", VERSION)), "{}", loud);
+        assert!(loud.contains("gk repipe - Update Concourse pipelines"), "{}", loud);
+
+        let mut quiet = Vec::new();
+        write_welcome(&mut quiet, true).unwrap();
+        assert!(quiet.is_empty());
+    }
+}
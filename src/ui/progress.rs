@@ -1,11 +1,66 @@
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{sync::Mutex, time::Duration};
+use super::is_quiet;
+
+/// Bars a multi-kit run is drawing, which output from inside it must clear.
+static ACTIVE: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+/// Registers the bars being drawn, or forgets them with `None`.
+pub fn set_active(multi_progress: Option<MultiProgress>) {
+    *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) = multi_progress;
+}
+
+/// Runs `f` with the active bars, if any, cleared off the terminal, so its
+/// output and prompts aren't drawn over. Nested calls just run `f`.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    let active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let Some(multi_progress) = active else {
+        return f();
+    };
+    let result = multi_progress.suspend(f);
+    set_active(Some(multi_progress));
+    result
+}
+
+/// A bar wired into `multi_progress`, or a hidden one under `--quiet`.
+fn add_bar(multi_progress: &MultiProgress, bar: ProgressBar) -> ProgressBar {
+    if is_quiet() {
+        ProgressBar::hidden()
+    } else {
+        multi_progress.add(bar)
+    }
+}
 
 pub fn create_progress_bar(multi_progress: &MultiProgress, len: u64, message: &str) -> ProgressBar {
-    let pb = multi_progress.add(ProgressBar::new(len));
+    let pb = add_bar(multi_progress, ProgressBar::new(len));
     pb.set_style(ProgressStyle::default_bar()
         .template("{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
         .unwrap()
         .progress_chars("=>-"));
     pb.set_message(message.to_string());
     pb
-}
\ No newline at end of file
+}
+pub fn create_spinner(multi_progress: &MultiProgress, prefix: &str, message: &str) -> ProgressBar {
+    let pb = add_bar(multi_progress, ProgressBar::new_spinner());
+    pb.set_style(ProgressStyle::default_spinner()
+        .template("{spinner:.green} {prefix:.bold} {msg}")
+        .unwrap());
+    pb.set_prefix(prefix.to_string());
+    pb.set_message(message.to_string());
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indicatif::ProgressDrawTarget;
+
+    #[test]
+    fn nested_suspends_run_once_and_keep_the_bars_active() {
+        set_active(Some(MultiProgress::with_draw_target(ProgressDrawTarget::hidden())));
+        assert_eq!(suspend(|| suspend(|| 42)), 42);
+        assert!(ACTIVE.lock().unwrap().is_some());
+        set_active(None);
+    }
+}
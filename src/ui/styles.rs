@@ -1,25 +1,155 @@
-use console::style;
+use console::{style, Color, Style};
+use dialoguer::theme::ColorfulTheme;
+use serde::Deserialize;
+use crate::config::config;
+
+/// `theme:` in the gkm config: which [`Palette`] the style helpers and prompts use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    /// Darker shades that stay readable on a light background
+    Light,
+    /// No color at all, as with `--no-color`
+    None,
+}
+
+/// The colors behind the style helpers below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub heading: Color,
+    pub param: Color,
+    pub command: Color,
+    pub info: Color,
+    pub success: Color,
+    pub warning: Color,
+    pub error: Color,
+    pub logo: Color,
+}
+
+const DARK: Palette = Palette {
+    heading: Color::Magenta,
+    param: Color::Yellow,
+    command: Color::Blue,
+    info: Color::Cyan,
+    success: Color::Green,
+    warning: Color::Yellow,
+    error: Color::Red,
+    logo: Color::Cyan,
+};
+
+const LIGHT: Palette = Palette {
+    heading: Color::Magenta,
+    param: Color::Color256(94),
+    command: Color::Blue,
+    info: Color::Color256(25),
+    success: Color::Color256(28),
+    warning: Color::Color256(130),
+    error: Color::Red,
+    logo: Color::Color256(25),
+};
+
+impl Theme {
+    /// `none` keeps the dark palette; colors are switched off globally instead.
+    pub fn palette(self) -> Palette {
+        match self {
+            Theme::Light => LIGHT,
+            Theme::Dark | Theme::None => DARK,
+        }
+    }
+}
+
+/// The configured palette.
+pub fn palette() -> Palette {
+    config().theme.palette()
+}
+
+/// A dialoguer theme in the configured palette's colors.
+pub fn dialoguer_theme() -> ColorfulTheme {
+    let palette = palette();
+    ColorfulTheme {
+        defaults_style: Style::new().for_stderr().fg(palette.info),
+        prompt_prefix: style("?".to_string()).for_stderr().fg(palette.param),
+        success_prefix: style("✔".to_string()).for_stderr().fg(palette.success),
+        values_style: Style::new().for_stderr().fg(palette.success),
+        active_item_style: Style::new().for_stderr().fg(palette.info),
+        active_item_prefix: style("❯".to_string()).for_stderr().fg(palette.success),
+        checked_item_prefix: style("✔".to_string()).for_stderr().fg(palette.success),
+        picked_item_prefix: style("❯".to_string()).for_stderr().fg(palette.success),
+        ..ColorfulTheme::default()
+    }
+}
 
 pub fn heading(text: &str) -> String {
-    style(text).magenta().bold().to_string()
+    style(text).fg(palette().heading).bold().to_string()
 }
 
 pub fn param(text: &str) -> String {
-    style(text).yellow().italic().to_string()
+    style(text).fg(palette().param).italic().to_string()
 }
 
 pub fn command(text: &str) -> String {
-    style(text).blue().bold().to_string()
+    style(text).fg(palette().command).bold().to_string()
 }
 
 pub fn info(text: &str) -> String {
-    style(text).cyan().to_string()
+    style(text).fg(palette().info).to_string()
+}
+
+/// A line reporting something that worked, prefixed with a green ✓.
+pub fn success(text: &str) -> String {
+    style(format!("✓ {}", text)).fg(palette().success).to_string()
+}
+
+/// A line reporting something worth a look, prefixed with a yellow ⚠.
+pub fn warning(text: &str) -> String {
+    style(format!("⚠ {}", text)).fg(palette().warning).to_string()
+}
+
+/// A line reporting a failure, prefixed with a red ⨯.
+pub fn error_line(text: &str) -> String {
+    style(format!("⨯ {}", text)).fg(palette().error).to_string()
 }
 
 pub fn style_logo(text: &str) -> String {
-    style(text).cyan().bold().to_string()
+    style(text).fg(palette().logo).bold().to_string()
 }
 
 pub fn style_version(text: &str) -> String {
     style(text).dim().to_string()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_lines_carry_their_prefix_and_color() {
+        for (line, expected) in [(success("set"), "✓ set"), (warning("stale"), "⚠ stale"), (error_line("failed"), "⨯ failed")] {
+            assert_eq!(console::strip_ansi_codes(&line), expected);
+        }
+
+        console::set_colors_enabled(true);
+        let palette = palette();
+        assert_eq!(success("set"), style("✓ set").fg(palette.success).to_string());
+        assert_eq!(warning("stale"), style("⚠ stale").fg(palette.warning).to_string());
+        assert_eq!(error_line("failed"), style("⨯ failed").fg(palette.error).to_string());
+        assert!(success("set").starts_with("\u{1b}["), "{:?} isn't colored", success("set"));
+    }
+
+    #[test]
+    fn light_theme_uses_different_codes_than_dark() {
+        let (dark, light) = (Theme::Dark.palette(), Theme::Light.palette());
+        assert_ne!(dark, light);
+        assert_eq!(Theme::None.palette(), dark);
+
+        console::set_colors_enabled(true);
+        let paint = |color| style("ok").fg(color).to_string();
+        assert_eq!(paint(dark.info), "\u{1b}[36mok\u{1b}[0m");
+        assert_eq!(paint(light.info), "\u{1b}[38;5;25mok\u{1b}[0m");
+        for (dark, light) in [(dark.param, light.param), (dark.success, light.success), (dark.warning, light.warning), (dark.logo, light.logo)] {
+            assert_ne!(paint(dark), paint(light));
+        }
+    }
+}